@@ -0,0 +1,96 @@
+(function () {
+	const svg = document.querySelector("#chart > svg");
+	const nodes = Array.from(svg.querySelectorAll("rect.node"));
+	const edges = Array.from(svg.querySelectorAll("g.edge"));
+	const tooltip = document.getElementById("tooltip");
+	const list = document.getElementById("subjects");
+
+	const labelOf = (id) => {
+		const node = svg.querySelector(`rect.node[data-node="${id}"]`);
+		return (node && node.dataset.label) || id;
+	};
+	const subjectsOf = (edge) => (edge.dataset.subjects || "").split(" ").filter((s) => s);
+
+	let selected = null;
+
+	// Dim every ribbon that does not satisfy `predicate`
+	const highlight = (predicate) => {
+		svg.classList.add("highlighting");
+		edges.forEach((edge) => edge.classList.toggle("highlighted", predicate(edge)));
+	};
+
+	const restore = () => {
+		if (selected === null) {
+			svg.classList.remove("highlighting");
+			edges.forEach((edge) => edge.classList.remove("highlighted"));
+		} else {
+			highlight((edge) => subjectsOf(edge).includes(selected));
+		}
+	};
+
+	const select = (subject) => {
+		selected = subject;
+		list.querySelectorAll("button").forEach((button) =>
+			button.classList.toggle("selected", button.dataset.subject === subject)
+		);
+		restore();
+	};
+
+	nodes.forEach((node) => {
+		const id = node.dataset.node;
+		node.addEventListener("mouseenter", () =>
+			highlight((edge) => edge.dataset.source === id || edge.dataset.target === id)
+		);
+		node.addEventListener("mouseleave", restore);
+	});
+
+	edges.forEach((edge) => {
+		edge.addEventListener("mousemove", (event) => {
+			const subjects = subjectsOf(edge);
+			const lines = [
+				`${labelOf(edge.dataset.source)} → ${labelOf(edge.dataset.target)}`,
+				`Count: ${edge.dataset.value}`,
+			];
+			if (subjects.length > 0) {
+				lines.push(`Subjects: ${subjects.join(", ")}`);
+			}
+			tooltip.replaceChildren(
+				...lines.map((line) => {
+					const div = document.createElement("div");
+					div.textContent = line;
+					return div;
+				})
+			);
+			tooltip.style.left = `${event.clientX + 12}px`;
+			tooltip.style.top = `${event.clientY + 12}px`;
+			tooltip.hidden = false;
+		});
+		edge.addEventListener("mouseleave", () => {
+			tooltip.hidden = true;
+		});
+		edge.addEventListener("click", () => {
+			const subjects = subjectsOf(edge);
+			if (subjects.length === 1) {
+				select(subjects[0] === selected ? null : subjects[0]);
+			}
+		});
+	});
+
+	const subjects = [...new Set(edges.flatMap(subjectsOf))].sort();
+	if (subjects.length === 0) {
+		list.hidden = true;
+	}
+	subjects.forEach((subject) => {
+		const button = document.createElement("button");
+		button.dataset.subject = subject;
+		button.textContent = subject;
+		button.addEventListener("click", () => select(subject === selected ? null : subject));
+		list.appendChild(button);
+	});
+
+	document.addEventListener("keydown", (event) => {
+		if (event.key === "Escape") {
+			select(null);
+		}
+	});
+})();
@@ -0,0 +1,390 @@
+use svg::{
+    node::{
+        self,
+        element::{path, Element, Group, Path, Rectangle, Style, Text, SVG},
+    },
+    Node,
+};
+
+pub struct AlluvialStyle<F: Fn(f64) -> String> {
+    pub number_format: Option<F>,
+    pub stratum_width: Option<f64>,
+    pub stratum_separation: Option<f64>,
+    pub font_family: Option<String>,
+    pub font_size: Option<f64>,
+    pub font_color: Option<String>,
+    pub border: Option<f64>,
+}
+
+impl<F: Fn(f64) -> String> Default for AlluvialStyle<F> {
+    fn default() -> Self {
+        AlluvialStyle {
+            number_format: None,
+            stratum_width: None,
+            stratum_separation: None,
+            font_family: None,
+            font_size: None,
+            font_color: None,
+            border: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AlluvialAxisID(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct AlluvialStratumID(usize);
+
+pub struct AlluvialAxis {
+    label: String,
+}
+
+pub struct AlluvialStratum {
+    label: String,
+    color: Option<String>,
+}
+
+/// One subject's path through the diagram: the stratum it occupies on each axis, if any.
+pub struct AlluvialLode {
+    subject: String,
+    color: Option<String>,
+    strata: Vec<Option<AlluvialStratumID>>,
+}
+
+/// An alluvial diagram, with one vertical axis per step (e.g. per dose).
+///
+/// Unlike a Sankey, every axis shows all strata in the same fixed order, and each subject is
+/// drawn as an individual lode. Within a stratum, lodes are stacked in one global order (by
+/// their whole trajectory, then by subject), so a subject keeps its relative position from one
+/// axis to the next.
+#[derive(Default)]
+pub struct Alluvial {
+    axes: Vec<AlluvialAxis>,
+    strata: Vec<AlluvialStratum>,
+    lodes: Vec<AlluvialLode>,
+}
+
+impl Alluvial {
+    pub fn new() -> Alluvial {
+        Alluvial::default()
+    }
+
+    /// Adds an axis to the right of the existing ones.
+    pub fn axis(&mut self, label: String) -> AlluvialAxisID {
+        let id = self.axes.len();
+        self.axes.push(AlluvialAxis { label });
+        for lode in &mut self.lodes {
+            lode.strata.push(None);
+        }
+        AlluvialAxisID(id)
+    }
+
+    /// Adds a stratum below the existing ones.
+    pub fn stratum(&mut self, label: String, color: Option<String>) -> AlluvialStratumID {
+        let id = self.strata.len();
+        self.strata.push(AlluvialStratum { label, color });
+        AlluvialStratumID(id)
+    }
+
+    /// Records that `subject` was in `stratum` at `axis`. Lodes are coloured with the subject's
+    /// colour if one is given, and otherwise with the colour of the stratum they leave from.
+    pub fn observe(
+        &mut self,
+        subject: &str,
+        color: Option<String>,
+        axis: AlluvialAxisID,
+        stratum: AlluvialStratumID,
+    ) {
+        let axis_count = self.axes.len();
+        let lode = match self.lodes.iter_mut().position(|l| l.subject == subject) {
+            Some(index) => &mut self.lodes[index],
+            None => {
+                self.lodes.push(AlluvialLode {
+                    subject: subject.to_string(),
+                    color: None,
+                    strata: vec![None; axis_count],
+                });
+                self.lodes.last_mut().unwrap()
+            }
+        };
+        if color.is_some() {
+            lode.color = color;
+        }
+        lode.strata[axis.0] = Some(stratum);
+    }
+
+    pub fn count(&self, axis: AlluvialAxisID, stratum: AlluvialStratumID) -> usize {
+        self.lodes
+            .iter()
+            .filter(|lode| lode.strata[axis.0] == Some(stratum))
+            .count()
+    }
+
+    pub fn draw<F: Fn(f64) -> String>(
+        &self,
+        width: f64,
+        height: f64,
+        style: AlluvialStyle<F>,
+    ) -> SVG {
+        let stratum_width = style.stratum_width.unwrap_or(width / 20.0);
+        let stratum_separation = style.stratum_separation.unwrap_or(height / 40.0);
+        let font_family: String = style.font_family.unwrap_or("sans-serif".to_string());
+        let font_size: f64 = style.font_size.unwrap_or(height / 50.0);
+        let font_color: String = style.font_color.unwrap_or("#000".to_string());
+        let border: f64 = style.border.unwrap_or(height / 10.0);
+
+        // Initialise SVG
+
+        let mut document = SVG::new();
+
+        document.assign("viewBox", (0.0, 0.0, width, height));
+
+        document.append(Style::new(format!(
+            "rect.stratum {{
+	fill: #FFFF;
+	stroke: #000F;
+}}
+
+.lode > path {{
+	fill: #0004;
+}}
+
+text.stratum, text.axis {{
+	fill: {font_color};
+	text-anchor: middle;
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}"
+        )));
+
+        // Order lodes globally by trajectory, so that their order within a stratum is the same on
+        // every axis
+
+        let mut order: Vec<usize> = (0..self.lodes.len()).collect();
+        order.sort_by(|&a, &b| {
+            let (a, b) = (&self.lodes[a], &self.lodes[b]);
+            a.strata
+                .cmp(&b.strata)
+                .then_with(|| a.subject.cmp(&b.subject))
+        });
+
+        // Scale so that the fullest axis fills the height
+
+        let axis_totals: Vec<usize> = (0..self.axes.len())
+            .map(|axis| {
+                self.lodes
+                    .iter()
+                    .filter(|lode| lode.strata[axis].is_some())
+                    .count()
+            })
+            .collect();
+        let max_total = axis_totals.iter().copied().max().unwrap_or(0);
+        let lode_height = (height
+            - border * 2.0
+            - stratum_separation * (self.strata.len().saturating_sub(1) as f64))
+            / (max_total.max(1) as f64);
+
+        let axis_spacing = match self.axes.len() > 1 {
+            true => (width - border * 2.0 - stratum_width) / ((self.axes.len() - 1) as f64),
+            false => 0.0,
+        };
+
+        // Position of each lode on each axis: lode_positions[lode][axis] = y
+        let mut lode_positions = vec![vec![None; self.axes.len()]; self.lodes.len()];
+
+        let mut svg_strata = Vec::new();
+        let mut svg_labels = Vec::new();
+
+        for (axis, &total) in axis_totals.iter().enumerate() {
+            let x = border + axis as f64 * axis_spacing;
+
+            let non_empty = (0..self.strata.len())
+                .filter(|&stratum| self.count(AlluvialAxisID(axis), AlluvialStratumID(stratum)) > 0)
+                .count();
+            let total_height = total as f64 * lode_height
+                + stratum_separation * (non_empty.saturating_sub(1) as f64);
+            let mut y = (height - total_height) / 2.0;
+
+            for (stratum_index, stratum) in self.strata.iter().enumerate() {
+                let stratum_id = Some(AlluvialStratumID(stratum_index));
+                let members: Vec<usize> = order
+                    .iter()
+                    .copied()
+                    .filter(|&lode| self.lodes[lode].strata[axis] == stratum_id)
+                    .collect();
+
+                if members.is_empty() {
+                    continue;
+                }
+
+                let stratum_height = members.len() as f64 * lode_height;
+
+                for (rank, &lode) in members.iter().enumerate() {
+                    lode_positions[lode][axis] = Some(y + rank as f64 * lode_height);
+                }
+
+                let mut rect = Rectangle::new();
+                rect.assign("x", x);
+                rect.assign("y", y);
+                rect.assign("width", stratum_width);
+                rect.assign("height", stratum_height);
+                rect.assign("class", "stratum");
+                if let Some(color) = stratum.color.as_deref() {
+                    rect.assign("style", format!("fill:{color}"));
+                }
+                svg_strata.push(rect);
+
+                let mid_x = x + stratum_width / 2.0;
+                let mid_y = y + stratum_height / 2.0;
+                let count = members.len() as f64;
+                let number = style
+                    .number_format
+                    .as_ref()
+                    .map_or(count.to_string(), |f| f(count));
+
+                let mut text = Text::new();
+                text.assign("x", mid_x);
+                text.assign("y", mid_y);
+                text.assign("class", "stratum");
+                let mut top = Element::new("tspan");
+                top.assign("x", mid_x);
+                top.assign("dy", -font_size / 2.0);
+                top.append(node::Text::new(&stratum.label));
+                text.append(top);
+                let mut bottom = Element::new("tspan");
+                bottom.assign("x", mid_x);
+                bottom.assign("dy", font_size);
+                bottom.append(node::Text::new(number));
+                text.append(bottom);
+                svg_labels.push(text);
+
+                y += stratum_height + stratum_separation;
+            }
+
+            let mut text = Text::new();
+            text.assign("x", x + stratum_width / 2.0);
+            text.assign("y", height - border / 2.0);
+            text.assign("class", "axis");
+            text.append(node::Text::new(&self.axes[axis].label));
+            svg_labels.push(text);
+        }
+
+        // Generate lodes, one group per subject
+
+        let mut svg_lodes = Vec::new();
+
+        for &lode_index in &order {
+            let lode = &self.lodes[lode_index];
+
+            let mut group = Group::new();
+            group.assign("class", "lode");
+            group.assign("data-subject", lode.subject.as_str());
+
+            for axis in 1..self.axes.len() {
+                let (Some(from_y), Some(to_y)) = (
+                    lode_positions[lode_index][axis - 1],
+                    lode_positions[lode_index][axis],
+                ) else {
+                    continue;
+                };
+
+                let from_x = border + (axis - 1) as f64 * axis_spacing + stratum_width;
+                let to_x = border + axis as f64 * axis_spacing;
+                let mid_x = (from_x + to_x) / 2.0;
+
+                let mut path = Path::new();
+                path.assign(
+                    "d",
+                    path::Data::new()
+                        .move_to((from_x, from_y))
+                        .cubic_curve_to((mid_x, from_y, mid_x, to_y, to_x, to_y))
+                        .line_to((to_x, to_y + lode_height))
+                        .cubic_curve_to((
+                            mid_x,
+                            to_y + lode_height,
+                            mid_x,
+                            from_y + lode_height,
+                            from_x,
+                            from_y + lode_height,
+                        ))
+                        .close(),
+                );
+                let color = lode.color.as_deref().or_else(|| {
+                    lode.strata[axis - 1]
+                        .and_then(|stratum| self.strata[stratum.0].color.as_deref())
+                });
+                if let Some(color) = color {
+                    path.assign("style", format!("fill:{color}"));
+                }
+                group.append(path);
+            }
+
+            svg_lodes.push(group);
+        }
+
+        // Add to SVG
+
+        for lode in svg_lodes {
+            document.append(lode);
+        }
+
+        for stratum in svg_strata {
+            document.append(stratum);
+        }
+
+        for label in svg_labels {
+            document.append(label);
+        }
+
+        document
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lodes_keep_their_order_across_axes() {
+        let mut alluvial = Alluvial::new();
+        let d1 = alluvial.axis("D1".to_string());
+        let d2 = alluvial.axis("D2".to_string());
+        let g0 = alluvial.stratum("G0".to_string(), None);
+        let g1 = alluvial.stratum("G1".to_string(), None);
+
+        for (subject, first, second) in [("c", g0, g0), ("a", g0, g0), ("b", g1, g0)] {
+            alluvial.observe(subject, None, d1, first);
+            alluvial.observe(subject, None, d2, second);
+        }
+        // A subject that only received the first dose
+        alluvial.observe("d", None, d1, g1);
+
+        assert_eq!(alluvial.count(d1, g0), 2);
+        assert_eq!(alluvial.count(d2, g0), 3);
+
+        let svg = alluvial
+            .draw(
+                400.0,
+                300.0,
+                AlluvialStyle::<fn(f64) -> String> {
+                    stratum_separation: Some(10.0),
+                    border: Some(20.0),
+                    ..AlluvialStyle::default()
+                },
+            )
+            .to_string();
+
+        // Lodes are drawn in the order a, c, d, b: a missing observation sorts first
+        let positions: Vec<usize> = ["\"a\"", "\"c\"", "\"d\"", "\"b\""]
+            .iter()
+            .map(|subject| svg.find(&format!("data-subject={subject}")).unwrap())
+            .collect();
+        assert!(positions.windows(2).all(|w| w[0] < w[1]));
+
+        // "d" has no second observation, so its lode has no ribbon
+        assert_eq!(svg.matches("<path").count(), 3);
+        assert_eq!(svg.matches("class=\"stratum\"").count(), 6);
+    }
+}
@@ -0,0 +1,108 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use svgdom::{Attribute, AttributeId, AttributeValue, Document, FilterSvg};
+
+use crate::file_op::{read_json_file, JsonReadError};
+use crate::models::crs_dose::AeDoseColorHashCsvRecord;
+
+#[derive(thiserror::Error, Debug)]
+pub enum AnnotateError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    ColorMap(#[from] JsonReadError),
+    #[error("SVG parsing error: {0}")]
+    Parsing(String),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PaintAttribute {
+    Fill,
+    // ggplot2 draws each alluvium's outline in the subject's colour, so this is the default.
+    Stroke,
+}
+
+/// Subjects drawn in each colour, keyed by upper-case hex colour.
+pub fn subjects_by_color(records: &[AeDoseColorHashCsvRecord]) -> BTreeMap<String, Vec<i32>> {
+    let mut subjects = BTreeMap::<String, Vec<i32>>::new();
+
+    for record in records {
+        let ids = subjects
+            .entry(record.color_hash.to_uppercase())
+            .or_default();
+        if !ids.contains(&record.subject_id) {
+            ids.push(record.subject_id);
+        }
+    }
+
+    subjects
+}
+
+pub fn read_color_map<P: AsRef<Path>>(
+    path: P,
+) -> Result<BTreeMap<String, Vec<i32>>, AnnotateError> {
+    let records: Vec<AeDoseColorHashCsvRecord> = read_json_file(path)?;
+
+    Ok(subjects_by_color(&records))
+}
+
+/// Tags every element of an externally rendered SVG (such as a ggalluvial plot) whose `paint`
+/// colour appears in `color_map` with a `data-subjects` attribute listing the subjects drawn in
+/// that colour. Returns the annotated SVG and the number of elements tagged.
+pub fn annotate_svg(
+    svg: &str,
+    color_map: &BTreeMap<String, Vec<i32>>,
+    paint: PaintAttribute,
+) -> Result<(String, usize), AnnotateError> {
+    let document =
+        Document::from_str(svg).map_err(|error| AnnotateError::Parsing(error.to_string()))?;
+
+    let attribute_id = match paint {
+        PaintAttribute::Fill => AttributeId::Fill,
+        PaintAttribute::Stroke => AttributeId::Stroke,
+    };
+
+    let mut count = 0;
+    for (_, mut node) in document.root().descendants().svg() {
+        let color = match node.attributes().get_value(attribute_id) {
+            Some(AttributeValue::Color(color)) => color.to_string().to_uppercase(),
+            _ => continue,
+        };
+
+        if let Some(subjects) = color_map.get(&color) {
+            let subjects = subjects
+                .iter()
+                .map(|subject| subject.to_string())
+                .collect::<Vec<_>>()
+                .join(" ");
+            node.set_attribute(Attribute::new("data-subjects", subjects));
+            count += 1;
+        }
+    }
+
+    Ok((document.to_string(), count))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn elements_are_tagged_with_their_subjects() {
+        let mut color_map = BTreeMap::new();
+        color_map.insert("#CCFF00".to_string(), vec![1, 2]);
+
+        let svg = r##"<svg xmlns="http://www.w3.org/2000/svg">
+<polygon points="0,0 1,1 1,0" style="stroke: #ccff00; fill: #F8766D;"/>
+<polygon points="0,0 1,1 1,0" style="stroke: #000000; fill: #CCFF00;"/>
+</svg>"##;
+
+        let (annotated, count) = annotate_svg(svg, &color_map, PaintAttribute::Stroke).unwrap();
+        assert_eq!(count, 1);
+        assert!(annotated.contains("data-subjects=\"1 2\""));
+
+        let (_, count) = annotate_svg(svg, &color_map, PaintAttribute::Fill).unwrap();
+        assert_eq!(count, 1);
+    }
+}
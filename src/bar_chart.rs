@@ -0,0 +1,525 @@
+use std::fmt::Display;
+
+use petgraph::graph::Graph;
+use petgraph::{Directed, Direction};
+use svg::{
+    node::{
+        self,
+        element::{Group, Line, Rectangle, Style, Text, SVG},
+    },
+    Node,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BarMode {
+    // Series stacked on top of each other, one bar per category.
+    Stacked,
+    // Series side by side within each category.
+    Grouped,
+    // Like `Stacked`, but every bar is scaled to 100%.
+    Percent,
+}
+
+pub struct BarChartStyle<F: Fn(f64) -> String> {
+    pub number_format: Option<F>,
+    pub mode: Option<BarMode>,
+    /// Fraction of each category's width left empty between bars.
+    pub bar_separation: Option<f64>,
+    /// Approximate number of ticks on the value axis.
+    pub ticks: Option<usize>,
+    pub x_label: Option<String>,
+    pub y_label: Option<String>,
+    pub font_family: Option<String>,
+    pub font_size: Option<f64>,
+    pub font_color: Option<String>,
+    pub border: Option<f64>,
+}
+
+impl<F: Fn(f64) -> String> Default for BarChartStyle<F> {
+    fn default() -> Self {
+        BarChartStyle {
+            number_format: None,
+            mode: None,
+            bar_separation: None,
+            ticks: None,
+            x_label: None,
+            y_label: None,
+            font_family: None,
+            font_size: None,
+            font_color: None,
+            border: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BarCategoryID(usize);
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BarSeriesID(usize);
+
+pub struct BarSeries {
+    label: String,
+    color: Option<String>,
+}
+
+/// Values grouped by category (along the x axis) and series (stacked or side by side).
+#[derive(Default)]
+pub struct BarChart {
+    categories: Vec<String>,
+    series: Vec<BarSeries>,
+    // values[series][category]
+    values: Vec<Vec<f64>>,
+}
+
+impl BarChart {
+    pub fn new() -> BarChart {
+        BarChart::default()
+    }
+
+    pub fn category(&mut self, label: String) -> BarCategoryID {
+        self.categories.push(label);
+        for values in &mut self.values {
+            values.push(0.0);
+        }
+        BarCategoryID(self.categories.len() - 1)
+    }
+
+    pub fn series(&mut self, label: String, color: Option<String>) -> BarSeriesID {
+        self.series.push(BarSeries { label, color });
+        self.values.push(vec![0.0; self.categories.len()]);
+        BarSeriesID(self.series.len() - 1)
+    }
+
+    pub fn add(&mut self, category: BarCategoryID, series: BarSeriesID, value: f64) {
+        self.values[series.0][category.0] += value;
+    }
+
+    pub fn value(&self, category: BarCategoryID, series: BarSeriesID) -> f64 {
+        self.values[series.0][category.0]
+    }
+
+    pub fn category_total(&self, category: BarCategoryID) -> f64 {
+        self.values.iter().map(|values| values[category.0]).sum()
+    }
+
+    pub fn draw<F: Fn(f64) -> String>(
+        &self,
+        width: f64,
+        height: f64,
+        style: BarChartStyle<F>,
+    ) -> SVG {
+        let mode = style.mode.unwrap_or(BarMode::Stacked);
+        let bar_separation = style.bar_separation.unwrap_or(0.3);
+        let ticks = style.ticks.unwrap_or(5);
+        let font_family: String = style.font_family.unwrap_or("sans-serif".to_string());
+        let font_size: f64 = style.font_size.unwrap_or(height / 50.0);
+        let font_color: String = style.font_color.unwrap_or("#000".to_string());
+        let border: f64 = style.border.unwrap_or(height / 10.0);
+
+        let format = |value: f64| match mode {
+            BarMode::Percent => format!("{value}%"),
+            _ => style
+                .number_format
+                .as_ref()
+                .map_or(value.to_string(), |f| f(value)),
+        };
+
+        // Initialise SVG
+
+        let mut document = SVG::new();
+
+        document.assign("viewBox", (0.0, 0.0, width, height));
+
+        document.append(Style::new(format!(
+            "rect.bar {{
+	fill: #000F;
+}}
+
+line.grid {{
+	stroke: #0002;
+}}
+
+line.axis {{
+	stroke: #000F;
+}}
+
+text {{
+	fill: {font_color};
+	text-anchor: middle;
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}
+
+text.tick {{
+	text-anchor: end;
+}}
+
+text.legend {{
+	text-anchor: start;
+}}"
+        )));
+
+        // Bar heights, in value units: bars[category][series] = (start, end)
+
+        let bars: Vec<Vec<(f64, f64)>> = (0..self.categories.len())
+            .map(|category| {
+                let total = self.category_total(BarCategoryID(category));
+                let mut start = 0.0;
+                (0..self.series.len())
+                    .map(|series| {
+                        let value = self.values[series][category];
+                        match mode {
+                            BarMode::Grouped => (0.0, value),
+                            BarMode::Stacked => {
+                                start += value;
+                                (start - value, start)
+                            }
+                            BarMode::Percent => {
+                                let value = match total > 0.0 {
+                                    true => value / total * 100.0,
+                                    false => 0.0,
+                                };
+                                start += value;
+                                (start - value, start)
+                            }
+                        }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let max_value = match mode {
+            BarMode::Percent => 100.0,
+            _ => bars
+                .iter()
+                .flatten()
+                .map(|&(_, end)| end)
+                .fold(0.0, f64::max),
+        };
+        let tick_step = nice_step(max_value, ticks);
+        let axis_max = match mode {
+            BarMode::Percent => 100.0,
+            _ => ((max_value / tick_step).ceil() * tick_step).max(tick_step),
+        };
+
+        // Plot area, with room for the tick labels to the left and the legend to the right
+
+        let plot_left = border + font_size * 4.0;
+        let plot_right = width - border - font_size * 6.0;
+        let plot_top = border;
+        let plot_bottom = height - border;
+        let scale = (plot_bottom - plot_top) / axis_max;
+
+        // Generate axes
+
+        let mut axes = Group::new();
+        axes.assign("class", "axes");
+
+        for index in 0..=(axis_max / tick_step).round() as usize {
+            let tick = index as f64 * tick_step;
+            let y = plot_bottom - tick * scale;
+            axes.append(
+                Line::new()
+                    .set("x1", plot_left)
+                    .set("y1", y)
+                    .set("x2", plot_right)
+                    .set("y2", y)
+                    .set("class", "grid"),
+            );
+            let mut text = Text::new();
+            text.assign("x", plot_left - font_size / 2.0);
+            text.assign("y", y);
+            text.assign("class", "tick");
+            text.append(node::Text::new(format(tick)));
+            axes.append(text);
+        }
+
+        axes.append(
+            Line::new()
+                .set("x1", plot_left)
+                .set("y1", plot_bottom)
+                .set("x2", plot_right)
+                .set("y2", plot_bottom)
+                .set("class", "axis"),
+        );
+        axes.append(
+            Line::new()
+                .set("x1", plot_left)
+                .set("y1", plot_top)
+                .set("x2", plot_left)
+                .set("y2", plot_bottom)
+                .set("class", "axis"),
+        );
+
+        if let Some(label) = style.x_label {
+            let mut text = Text::new();
+            text.assign("x", (plot_left + plot_right) / 2.0);
+            text.assign("y", plot_bottom + font_size * 3.0);
+            text.append(node::Text::new(label));
+            axes.append(text);
+        }
+        if let Some(label) = style.y_label {
+            let x = plot_left - font_size * 3.5;
+            let y = (plot_top + plot_bottom) / 2.0;
+            let mut text = Text::new();
+            text.assign("x", x);
+            text.assign("y", y);
+            text.assign("transform", format!("rotate(-90 {x} {y})"));
+            text.append(node::Text::new(label));
+            axes.append(text);
+        }
+
+        // Generate bars
+
+        let mut svg_bars = Vec::new();
+        let mut svg_labels = Vec::new();
+
+        let category_width = (plot_right - plot_left) / self.categories.len().max(1) as f64;
+        let bar_width = category_width * (1.0 - bar_separation);
+
+        for (category, label) in self.categories.iter().enumerate() {
+            let category_x = plot_left + category as f64 * category_width;
+
+            let mut text = Text::new();
+            text.assign("x", category_x + category_width / 2.0);
+            text.assign("y", plot_bottom + font_size);
+            text.assign("class", "category");
+            text.append(node::Text::new(label));
+            svg_labels.push(text);
+
+            let series_width = match mode {
+                BarMode::Grouped => bar_width / self.series.len().max(1) as f64,
+                _ => bar_width,
+            };
+
+            for (series, &(start, end)) in bars[category].iter().enumerate() {
+                let x = category_x
+                    + category_width * bar_separation / 2.0
+                    + match mode {
+                        BarMode::Grouped => series as f64 * series_width,
+                        _ => 0.0,
+                    };
+
+                let mut rect = Rectangle::new();
+                rect.assign("x", x);
+                rect.assign("y", plot_bottom - end * scale);
+                rect.assign("width", series_width);
+                rect.assign("height", (end - start) * scale);
+                rect.assign("class", "bar");
+                rect.assign("data-category", label.as_str());
+                rect.assign("data-series", self.series[series].label.as_str());
+                rect.assign("data-value", self.values[series][category]);
+                rect.assign("style", series_fill(&self.series, series));
+                svg_bars.push(rect);
+            }
+        }
+
+        // Generate legend
+
+        let mut legend = Group::new();
+        legend.assign("class", "legend");
+
+        // Listed top to bottom in the same order as they are stacked
+        for (index, series) in (0..self.series.len()).rev().enumerate() {
+            let y = plot_top + index as f64 * font_size * 1.5;
+
+            let mut rect = Rectangle::new();
+            rect.assign("x", plot_right + font_size);
+            rect.assign("y", y - font_size / 2.0);
+            rect.assign("width", font_size);
+            rect.assign("height", font_size);
+            rect.assign("class", "bar");
+            rect.assign("style", series_fill(&self.series, series));
+            legend.append(rect);
+
+            let mut text = Text::new();
+            text.assign("x", plot_right + font_size * 2.5);
+            text.assign("y", y);
+            text.assign("class", "legend");
+            text.append(node::Text::new(&self.series[series].label));
+            legend.append(text);
+        }
+
+        // Add to SVG
+
+        document.append(axes);
+
+        for bar in svg_bars {
+            document.append(bar);
+        }
+
+        for label in svg_labels {
+            document.append(label);
+        }
+
+        document.append(legend);
+
+        document
+    }
+}
+
+// Series without a colour are drawn in shades of grey, from light to dark.
+fn series_fill(series: &[BarSeries], index: usize) -> String {
+    match series[index].color.as_deref() {
+        Some(color) => format!("fill:{color}"),
+        None => format!(
+            "fill-opacity:{:.3}",
+            (index + 1) as f64 / series.len() as f64
+        ),
+    }
+}
+
+/// Rounds `max / ticks` to 1, 2 or 5 times a power of ten.
+fn nice_step(max: f64, ticks: usize) -> f64 {
+    if max <= 0.0 {
+        return 1.0;
+    }
+    let raw = max / ticks.max(1) as f64;
+    let magnitude = 10f64.powf(raw.log10().floor());
+    [1.0, 2.0, 5.0, 10.0]
+        .into_iter()
+        .map(|factor| factor * magnitude)
+        .find(|&step| step >= raw)
+        .unwrap_or(10.0 * magnitude)
+}
+
+/// Builds a bar chart from the node totals of a transition graph, that is the larger of each
+/// node's inflow and outflow. Categories and series are ordered as first seen.
+pub fn convert_to_bar_chart<N: Clone + Display, E: Clone>(
+    graph: &Graph<N, E, Directed>,
+    node_category: &dyn Fn(N) -> String,
+    node_series: &dyn Fn(N) -> String,
+    edge_weight: &dyn Fn(&E) -> f64,
+) -> BarChart {
+    let mut chart = BarChart::new();
+    let mut categories: Vec<(String, BarCategoryID)> = Vec::new();
+    let mut series: Vec<(String, BarSeriesID)> = Vec::new();
+
+    for node in graph.node_indices() {
+        let weight = graph[node].clone();
+
+        let category_label = node_category(weight.clone());
+        let category = match categories
+            .iter()
+            .find(|(label, _)| *label == category_label)
+        {
+            Some(&(_, id)) => id,
+            None => {
+                let id = chart.category(category_label.clone());
+                categories.push((category_label, id));
+                id
+            }
+        };
+
+        let series_label = node_series(weight);
+        let series_id = match series.iter().find(|(label, _)| *label == series_label) {
+            Some(&(_, id)) => id,
+            None => {
+                let id = chart.series(series_label.clone(), None);
+                series.push((series_label, id));
+                id
+            }
+        };
+
+        let total = |direction| -> f64 {
+            graph
+                .edges_directed(node, direction)
+                .map(|edge| edge_weight(edge.weight()))
+                .sum()
+        };
+        let flow = f64::max(total(Direction::Incoming), total(Direction::Outgoing));
+
+        chart.add(category, series_id, flow);
+    }
+
+    chart
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn totals_nodes_by_category_and_series() {
+        let mut graph = Graph::<&str, f64, Directed>::new();
+        let d1g0 = graph.add_node("D1.G0");
+        let d1g1 = graph.add_node("D1.G1");
+        let d2g0 = graph.add_node("D2.G0");
+        let d2g1 = graph.add_node("D2.G1");
+        graph.add_edge(d1g0, d2g0, 3.0);
+        graph.add_edge(d1g0, d2g1, 1.0);
+        graph.add_edge(d1g1, d2g0, 1.0);
+
+        let chart = convert_to_bar_chart(
+            &graph,
+            &|n| n[..2].to_string(),
+            &|n| n[3..].to_string(),
+            &|&w| w,
+        );
+
+        let (d1, d2) = (BarCategoryID(0), BarCategoryID(1));
+        let (g0, g1) = (BarSeriesID(0), BarSeriesID(1));
+        assert_eq!(chart.value(d1, g0), 4.0);
+        assert_eq!(chart.value(d1, g1), 1.0);
+        assert_eq!(chart.value(d2, g0), 4.0);
+        assert_eq!(chart.value(d2, g1), 1.0);
+        assert_eq!(chart.category_total(d1), 5.0);
+
+        assert_eq!(nice_step(5.0, 5), 1.0);
+        assert_eq!(nice_step(37.0, 5), 10.0);
+        assert_eq!(nice_step(130.0, 5), 50.0);
+
+        for mode in [BarMode::Stacked, BarMode::Grouped, BarMode::Percent] {
+            let svg = chart
+                .draw(
+                    600.0,
+                    400.0,
+                    BarChartStyle::<fn(f64) -> String> {
+                        mode: Some(mode),
+                        ..BarChartStyle::default()
+                    },
+                )
+                .to_string();
+            assert_eq!(svg.matches("data-series=").count(), 4);
+            assert_eq!(svg.contains("100%"), mode == BarMode::Percent);
+
+            // [x, y, width, height] of the G0 and G1 bars of D1
+            let [g0, g1] = ["G0", "G1"].map(|series| bar_rect(&svg, "D1", series));
+            let bottom = g0[1] + g0[3];
+            match mode {
+                BarMode::Grouped => {
+                    assert_eq!(g1[0], g0[0] + g0[2]);
+                    assert_eq!(g1[1] + g1[3], bottom);
+                    assert_eq!(g0[3], 4.0 * g1[3]);
+                }
+                BarMode::Stacked => {
+                    assert_eq!(g1[0], g0[0]);
+                    assert_eq!(g1[1] + g1[3], g0[1]);
+                }
+                BarMode::Percent => {
+                    assert_eq!(g1[1] + g1[3], g0[1]);
+                    // The plot spans the height between the default borders of a tenth
+                    assert!((g0[3] + g1[3] - 400.0 * 0.8).abs() < 1e-9);
+                }
+            }
+        }
+    }
+
+    // The x, y, width and height of the bar of a category and series
+    fn bar_rect(svg: &str, category: &str, series: &str) -> [f64; 4] {
+        let rect = svg
+            .split("<rect")
+            .find(|rect| {
+                rect.contains(&format!("data-category=\"{category}\""))
+                    && rect.contains(&format!("data-series=\"{series}\""))
+            })
+            .unwrap();
+        let rect = &rect[..rect.find('>').unwrap()];
+        ["x", "y", "width", "height"].map(|name| {
+            let value = &rect[rect.find(&format!(" {name}=\"")).unwrap() + name.len() + 3..];
+            value[..value.find('"').unwrap()].parse().unwrap()
+        })
+    }
+}
@@ -0,0 +1,26 @@
+use charts::{
+    create_crs_graph, errors,
+    explorer::ExplorerApp,
+    models::{deserialise_from_file, AltGraph},
+};
+
+/// Opens the transition graph in an interactive viewer.
+///
+/// Usage: `explorer [graph.json]`. With no argument the graph is built from `./dose.csv`,
+/// otherwise it is read from a file in the `AltGraph` format.
+fn main() -> errors::Result<()> {
+    let graph = match std::env::args().nth(1) {
+        Some(path) => deserialise_from_file::<AltGraph>(path)?.to_graph()?,
+        None => create_crs_graph()?,
+    };
+
+    let app = ExplorerApp::new(&graph);
+
+    eframe::run_native(
+        "Transition graph explorer",
+        eframe::NativeOptions::default(),
+        Box::new(|_cc| Box::new(app)),
+    )?;
+
+    Ok(())
+}
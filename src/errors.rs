@@ -1,4 +1,4 @@
-use crate::models::EnumIntConversionError;
+use crate::models::{AltGraphError, DeserializeFromFileError, EnumIntConversionError};
 
 ///ThrandError  enumerates all possible errors returned by this library.
 #[derive(thiserror::Error, Debug)]
@@ -13,6 +13,57 @@ pub enum ChartAppErrors {
     JsonReadError(#[from] crate::file_op::JsonReadError),
     #[error(transparent)]
     EnumIntConversionError(#[from] EnumIntConversionError),
+    #[error(transparent)]
+    AltGraphError(#[from] AltGraphError),
+    #[error(transparent)]
+    DeserializeFromFileError(#[from] DeserializeFromFileError),
+    #[error(transparent)]
+    EframeError(#[from] eframe::Error),
+    #[error(transparent)]
+    ImageExportError(#[from] crate::export::image::ImageExportError),
+    #[error(transparent)]
+    WebImportError(#[from] crate::export::web::WebImportError),
+    #[error(transparent)]
+    PaletteError(#[from] crate::palette::PaletteError),
+    #[error(transparent)]
+    DoseCsvError(#[from] crate::models::crs_dose::DoseCsvError),
+    #[error(transparent)]
+    AnnotateError(#[from] crate::annotate::AnnotateError),
+    #[error(transparent)]
+    SpecError(#[from] crate::spec::SpecError),
+}
+
+impl ChartAppErrors {
+    /// Process exit status for the error, following the BSD `sysexits.h` convention: 65 for bad
+    /// input data, 74 for I/O failures and 1 for anything else.
+    pub fn exit_code(&self) -> u8 {
+        use crate::annotate::AnnotateError;
+        use crate::export::image::ImageExportError;
+        use crate::file_op::JsonReadError;
+        use crate::models::crs_dose::DoseCsvError;
+        use crate::spec::SpecError;
+
+        match self {
+            ChartAppErrors::IoError(_)
+            | ChartAppErrors::JsonReadError(JsonReadError::Io(_))
+            | ChartAppErrors::DeserializeFromFileError(DeserializeFromFileError::IoError(_))
+            | ChartAppErrors::ImageExportError(ImageExportError::Io(_))
+            | ChartAppErrors::AnnotateError(AnnotateError::Io(_))
+            | ChartAppErrors::SpecError(SpecError::Io(_))
+            | ChartAppErrors::AnnotateError(AnnotateError::ColorMap(JsonReadError::Io(_))) => 74,
+            ChartAppErrors::CsvError(error)
+            | ChartAppErrors::DoseCsvError(DoseCsvError::CsvError(error))
+                if error.is_io_error() =>
+            {
+                74
+            }
+            ChartAppErrors::DeserializeFromFileError(
+                DeserializeFromFileError::DeserialiseError(error),
+            ) if error.is_io() => 74,
+            ChartAppErrors::EframeError(_) => 1,
+            _ => 65,
+        }
+    }
 }
 
 pub type Result<T> = color_eyre::eyre::Result<T, ChartAppErrors>;
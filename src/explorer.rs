@@ -0,0 +1,352 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use egui::{CollapsingHeader, Context, DragValue, ScrollArea, Slider, Ui, Vec2};
+use egui_graphs::{Edge, GraphView, Node};
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::stable_graph::StableGraph;
+use petgraph::visit::EdgeRef;
+use petgraph::Directed;
+
+use crate::layout::{
+    force_directed_locations, layered_locations, ForceKind, ForceLayoutSettings, NodeLocations,
+};
+use crate::sankey_graph::SubjectFlow;
+use crate::settings::{SettingsGraph, SettingsInteraction, SettingsNavigation, SettingsStyle};
+
+const MAX_EDGE_WIDTH: f32 = 8.0;
+
+/// Graph shown by the explorer: parallel transitions are merged into one edge carrying the
+/// subjects that made them.
+pub type ExplorerGraph<N> = egui_graphs::Graph<N, Vec<String>, Directed>;
+
+/// Converts a transition graph into the representation drawn by `egui_graphs`.
+pub fn to_explorer_graph<N: Clone + Display, E: Clone + SubjectFlow>(
+    graph: &Graph<N, E, Directed>,
+    locations: &NodeLocations,
+) -> ExplorerGraph<N> {
+    let mut explorer_graph = StableGraph::new();
+
+    for node in graph.node_indices() {
+        let weight = graph[node].clone();
+        let (x, y) = locations.get(&node).copied().unwrap_or_default();
+        let location = Vec2::new(x as f32, y as f32);
+        let label = weight.to_string();
+        explorer_graph.add_node(Node::new(location, weight).with_label(label));
+    }
+
+    let mut transitions = BTreeMap::<(NodeIndex, NodeIndex), (f64, Vec<String>)>::new();
+    for edge in graph.edge_references() {
+        let (value, subjects) = transitions
+            .entry((edge.source(), edge.target()))
+            .or_default();
+        *value += edge.weight().value();
+        subjects.push(edge.weight().subject());
+    }
+
+    let max_value = transitions
+        .values()
+        .map(|(value, _)| *value)
+        .fold(0.0, f64::max);
+
+    // Node indices are preserved, since nodes were added in the same order
+    for ((source, target), (value, subjects)) in transitions {
+        // Every transition may carry a zero weight, which would otherwise give NaN widths
+        let share = match max_value > 0.0 {
+            true => (value / max_value) as f32,
+            false => 0.0,
+        };
+        let width = 1.0 + (MAX_EDGE_WIDTH - 1.0) * share;
+        explorer_graph.add_edge(source, target, Edge::new(subjects).with_width(width));
+    }
+
+    explorer_graph
+}
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum ExplorerLayout {
+    Layered,
+    ForceDirected,
+}
+
+pub struct ExplorerApp<N: Clone> {
+    graph: ExplorerGraph<N>,
+    // Kept to re-run the force-directed layout, which only needs the shape of the graph
+    structure: Graph<(), (), Directed>,
+
+    layout: ExplorerLayout,
+    layered_locations: NodeLocations,
+    force_directed_locations: NodeLocations,
+    force_settings: ForceLayoutSettings,
+
+    settings_graph: SettingsGraph,
+    settings_interaction: SettingsInteraction,
+    settings_navigation: SettingsNavigation,
+    settings_style: SettingsStyle,
+}
+
+impl<N: Clone + Display> ExplorerApp<N> {
+    pub fn new<E: Clone + SubjectFlow>(graph: &Graph<N, E, Directed>) -> Self {
+        let structure = graph.map(|_, _| (), |_, _| ());
+        let force_settings = ForceLayoutSettings::default();
+        let layered = layered_locations(graph);
+        let force_directed = force_directed_locations(&structure, &force_settings);
+
+        let graph = to_explorer_graph(graph, &layered);
+
+        ExplorerApp {
+            structure,
+            layout: ExplorerLayout::Layered,
+            layered_locations: layered,
+            force_directed_locations: force_directed,
+            force_settings,
+            settings_graph: SettingsGraph {
+                count_node: graph.node_count(),
+                count_edge: graph.edge_count(),
+            },
+            settings_interaction: SettingsInteraction {
+                dragging_enabled: true,
+                clicking_enabled: true,
+                selection_enabled: true,
+                ..SettingsInteraction::default()
+            },
+            settings_navigation: SettingsNavigation::default(),
+            settings_style: SettingsStyle::default(),
+            graph,
+        }
+    }
+
+    fn apply_layout(&mut self) {
+        let locations = match self.layout {
+            ExplorerLayout::Layered => &self.layered_locations,
+            ExplorerLayout::ForceDirected => &self.force_directed_locations,
+        };
+
+        for (node, &(x, y)) in locations {
+            if let Some(weight) = self.graph.node_weight_mut(*node) {
+                weight.set_location(Vec2::new(x as f32, y as f32));
+            }
+        }
+    }
+
+    fn draw_section_graph(&mut self, ui: &mut Ui) {
+        CollapsingHeader::new("Graph")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.label(format!("Nodes: {}", self.settings_graph.count_node));
+                ui.label(format!("Transitions: {}", self.settings_graph.count_edge));
+
+                let previous = self.layout;
+                ui.radio_value(&mut self.layout, ExplorerLayout::Layered, "layered");
+                ui.radio_value(
+                    &mut self.layout,
+                    ExplorerLayout::ForceDirected,
+                    "force directed",
+                );
+                if self.layout != previous {
+                    self.apply_layout();
+                }
+            });
+    }
+
+    fn draw_section_force_layout(&mut self, ui: &mut Ui) {
+        let settings = &mut self.force_settings;
+        let mut changed = false;
+
+        CollapsingHeader::new("Force layout")
+            .default_open(false)
+            .show(ui, |ui| {
+                let mut handy = matches!(settings.force, ForceKind::Handy { .. });
+                changed |= ui
+                    .radio_value(&mut handy, false, "Fruchterman-Reingold")
+                    .changed();
+                changed |= ui.radio_value(&mut handy, true, "handy").changed();
+                settings.force = match (handy, settings.force) {
+                    (false, _) => ForceKind::FruchtermanReingold,
+                    (true, ForceKind::FruchtermanReingold) => ForceLayoutSettings::default().force,
+                    (true, force) => force,
+                };
+                if let ForceKind::Handy { gravity, centering } = &mut settings.force {
+                    changed |= ui.checkbox(gravity, "gravity").changed();
+                    changed |= ui.checkbox(centering, "centering").changed();
+                }
+
+                changed |= ui
+                    .add(Slider::new(&mut settings.iterations, 0..=2000).text("iterations"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut settings.dt, 0.001..=0.1).text("step length"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut settings.scale, 1.0..=200.0).text("scale"))
+                    .changed();
+                changed |= ui
+                    .add(Slider::new(&mut settings.cooloff_factor, 0.0..=1.0).text("cool-off"))
+                    .changed();
+                changed |= ui
+                    .add(
+                        Slider::new(&mut settings.node_start_size, 1.0..=1000.0).text("start size"),
+                    )
+                    .changed();
+                ui.horizontal(|ui| {
+                    ui.label("seed");
+                    changed |= ui.add(DragValue::new(&mut settings.seed)).changed();
+                });
+            });
+
+        if changed {
+            self.force_directed_locations =
+                force_directed_locations(&self.structure, &self.force_settings);
+            if self.layout == ExplorerLayout::ForceDirected {
+                self.apply_layout();
+            }
+        }
+    }
+
+    fn draw_section_navigation(&mut self, ui: &mut Ui) {
+        let settings = &mut self.settings_navigation;
+
+        CollapsingHeader::new("Navigation")
+            .default_open(true)
+            .show(ui, |ui| {
+                if ui
+                    .checkbox(&mut settings.fit_to_screen_enabled, "fit to screen")
+                    .changed()
+                    && settings.fit_to_screen_enabled
+                {
+                    settings.zoom_and_pan_enabled = false;
+                }
+                ui.add_enabled_ui(!settings.fit_to_screen_enabled, |ui| {
+                    ui.checkbox(&mut settings.zoom_and_pan_enabled, "zoom and pan");
+                });
+                ui.add(Slider::new(&mut settings.screen_padding, 0.0..=1.0).text("screen padding"));
+                ui.add(Slider::new(&mut settings.zoom_speed, 0.01..=1.0).text("zoom speed"));
+            });
+    }
+
+    fn draw_section_interaction(&mut self, ui: &mut Ui) {
+        let settings = &mut self.settings_interaction;
+
+        CollapsingHeader::new("Interaction")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.checkbox(&mut settings.dragging_enabled, "dragging");
+                ui.checkbox(&mut settings.clicking_enabled, "clicking");
+                ui.checkbox(&mut settings.selection_enabled, "selection");
+                ui.checkbox(&mut settings.selection_multi_enabled, "multi-selection");
+                ui.add_enabled_ui(settings.selection_enabled, |ui| {
+                    ui.add(
+                        Slider::new(&mut settings.selection_depth, -10..=10)
+                            .text("selection depth"),
+                    )
+                    .on_hover_text("Positive values select children, negative values parents");
+                });
+                ui.checkbox(&mut settings.folding_enabled, "folding");
+                ui.add_enabled_ui(settings.folding_enabled, |ui| {
+                    ui.add(Slider::new(&mut settings.folding_depth, 0..=10).text("folding depth"));
+                });
+            });
+    }
+
+    fn draw_section_style(&mut self, ui: &mut Ui) {
+        let settings = &mut self.settings_style;
+
+        CollapsingHeader::new("Style")
+            .default_open(true)
+            .show(ui, |ui| {
+                ui.checkbox(&mut settings.labels_always, "labels always");
+                ui.add(
+                    Slider::new(&mut settings.edge_radius_weight, 0.0..=5.0)
+                        .text("edge radius weight"),
+                );
+                ui.add(
+                    Slider::new(&mut settings.folded_node_radius_weight, 0.0..=10.0)
+                        .text("folded node radius weight"),
+                );
+            });
+    }
+
+    fn draw_selection(&self, ui: &mut Ui) {
+        CollapsingHeader::new("Selected")
+            .default_open(true)
+            .show(ui, |ui| {
+                ScrollArea::vertical().max_height(200.0).show(ui, |ui| {
+                    for node in self.graph.node_weights().filter(|node| node.selected()) {
+                        if let Some(label) = node.label() {
+                            ui.label(label);
+                        }
+                    }
+                });
+            });
+    }
+}
+
+impl<N: Clone + Display> eframe::App for ExplorerApp<N> {
+    fn update(&mut self, ctx: &Context, _frame: &mut eframe::Frame) {
+        egui::SidePanel::right("settings")
+            .min_width(250.0)
+            .show(ctx, |ui| {
+                ScrollArea::vertical().show(ui, |ui| {
+                    self.draw_section_graph(ui);
+                    self.draw_section_force_layout(ui);
+                    self.draw_section_navigation(ui);
+                    self.draw_section_interaction(ui);
+                    self.draw_section_style(ui);
+                    self.draw_selection(ui);
+                });
+            });
+
+        egui::CentralPanel::default().show(ctx, |ui| {
+            ui.add(
+                &mut GraphView::new(&mut self.graph)
+                    .with_interactions(&(&self.settings_interaction).into())
+                    .with_navigations(&(&self.settings_navigation).into())
+                    .with_styles(&(&self.settings_style).into()),
+            );
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::layout::LAYER_SPACING;
+    use crate::models::EdgeDatum;
+
+    #[test]
+    fn parallel_transitions_are_merged() {
+        let mut graph = Graph::<&str, EdgeDatum, Directed>::new();
+        let a = graph.add_node("a");
+        let b = graph.add_node("b");
+        graph.add_edge(
+            a,
+            b,
+            EdgeDatum {
+                weight: 1,
+                subject_id: 1,
+            },
+        );
+        graph.add_edge(
+            a,
+            b,
+            EdgeDatum {
+                weight: 1,
+                subject_id: 2,
+            },
+        );
+
+        let explorer_graph = to_explorer_graph(&graph, &layered_locations(&graph));
+
+        assert_eq!(explorer_graph.edge_count(), 1);
+        let edge = explorer_graph.edge_weights().next().unwrap();
+        assert_eq!(edge.data(), Some(&vec!["1".to_string(), "2".to_string()]));
+        assert_eq!(
+            explorer_graph[b].location(),
+            Vec2::new(LAYER_SPACING as f32, 0.0)
+        );
+
+        let weightless = graph.map(|_, &node| node, |_, &edge| EdgeDatum { weight: 0, ..edge });
+        let explorer_graph = to_explorer_graph(&weightless, &layered_locations(&weightless));
+        assert_eq!(explorer_graph.edge_weights().next().unwrap().width(), 1.0);
+    }
+}
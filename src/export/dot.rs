@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fmt::{Display, Write};
+
+use petgraph::graph::Graph;
+use petgraph::visit::EdgeRef;
+use petgraph::Directed;
+
+use crate::sankey::SankeyLayers;
+
+const MIN_PEN_WIDTH: f64 = 1.0;
+const MAX_PEN_WIDTH: f64 = 10.0;
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Writes a transition graph in Graphviz DOT format.
+///
+/// Nodes of the same layer are grouped into a `rank=same` subgraph so that `dot` lays them out in
+/// one column, mirroring our own layering. Parallel edges are merged into a single edge labelled
+/// with their summed weight, with a `penwidth` proportional to that weight.
+pub fn to_dot<N: Clone + Display, E: Clone>(
+    graph: &Graph<N, E, Directed>,
+    node_labeller: &dyn Fn(N) -> String,
+    node_colorer: &dyn Fn(N) -> Option<String>,
+    edge_weight: &dyn Fn(&E) -> f64,
+) -> String {
+    let mut layers = SankeyLayers::new(graph).collect_by_layer();
+    for nodes in layers.values_mut() {
+        nodes.sort();
+    }
+
+    let mut weights = BTreeMap::<(usize, usize), f64>::new();
+    for edge in graph.edge_references() {
+        *weights
+            .entry((edge.source().index(), edge.target().index()))
+            .or_default() += edge_weight(edge.weight());
+    }
+    let max_weight = weights.values().copied().fold(0.0, f64::max);
+
+    let node_statement = |node: petgraph::graph::NodeIndex| {
+        let weight = graph[node].clone();
+        let mut attributes = vec![format!("label={}", quote(&node_labeller(weight.clone())))];
+        if let Some(color) = node_colorer(weight) {
+            attributes.push(format!("fillcolor={}", quote(&color)));
+            attributes.push("style=filled".to_string());
+        }
+        format!("{} [ {} ]", node.index(), attributes.join(", "))
+    };
+
+    let mut dot = String::new();
+
+    writeln!(dot, "digraph {{").unwrap();
+    writeln!(dot, "    rankdir=LR").unwrap();
+    writeln!(dot, "    node [ shape=box ]").unwrap();
+
+    for (layer, nodes) in &layers {
+        writeln!(dot, "    subgraph layer_{layer} {{").unwrap();
+        writeln!(dot, "        rank=same").unwrap();
+        for &node in nodes {
+            writeln!(dot, "        {}", node_statement(node)).unwrap();
+        }
+        writeln!(dot, "    }}").unwrap();
+    }
+
+    // Nodes on a cycle are never visited by the topological walk, so have no layer
+    for node in graph.node_indices() {
+        if !layers.values().any(|nodes| nodes.contains(&node)) {
+            writeln!(dot, "    {}", node_statement(node)).unwrap();
+        }
+    }
+
+    for ((source, target), weight) in weights {
+        let pen_width = match max_weight > 0.0 {
+            true => MIN_PEN_WIDTH + (MAX_PEN_WIDTH - MIN_PEN_WIDTH) * weight / max_weight,
+            false => MIN_PEN_WIDTH,
+        };
+        writeln!(
+            dot,
+            "    {source} -> {target} [ label={}, penwidth={pen_width:.2} ]",
+            quote(&weight.to_string())
+        )
+        .unwrap();
+    }
+
+    writeln!(dot, "}}").unwrap();
+
+    dot
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn groups_layers_and_merges_parallel_edges() {
+        let mut graph = Graph::<&str, f64, Directed>::new();
+        let a = graph.add_node("a");
+        let b = graph.add_node("b");
+        let c = graph.add_node("c");
+        graph.add_edge(a, b, 1.0);
+        graph.add_edge(a, b, 1.0);
+        graph.add_edge(a, c, 2.0);
+        graph.add_edge(a, c, 2.0);
+
+        let dot = to_dot(
+            &graph,
+            &|n| n.to_string(),
+            &|n| (n == "a").then(|| "#ff0000".to_string()),
+            &|&w| w,
+        );
+
+        assert!(dot.contains(
+            "    subgraph layer_1 {\n        rank=same\n        1 [ label=\"b\" ]\n        2 [ label=\"c\" ]\n    }"
+        ));
+        assert!(dot.contains("0 [ label=\"a\", fillcolor=\"#ff0000\", style=filled ]"));
+        assert!(dot.contains("0 -> 1 [ label=\"2\", penwidth=5.50 ]"));
+        assert!(dot.contains("0 -> 2 [ label=\"4\", penwidth=10.00 ]"));
+        assert_eq!(dot.matches("->").count(), 2);
+    }
+}
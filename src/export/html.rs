@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use svg::node::element::SVG;
+
+static STYLE: &str = include_str!("../../assets/html/sankey.css");
+static SCRIPT: &str = include_str!("../../assets/html/sankey.js");
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Wraps a drawn Sankey diagram in a self-contained HTML page.
+///
+/// The page relies on the `data-*` attributes emitted by [`crate::sankey_graph::Sankey::draw`]:
+/// hovering a node highlights its ribbons, hovering a ribbon shows its endpoints, count and
+/// subjects, and selecting a subject (from the side panel, or by clicking a single-subject
+/// ribbon) highlights that subject's whole trajectory. Everything is inlined, so the file works
+/// offline.
+pub fn render_html(svg: &SVG, title: &str) -> String {
+    format!(
+        "<!DOCTYPE html>
+<html lang=\"en\">
+<head>
+<meta charset=\"utf-8\">
+<title>{title}</title>
+<style>
+{STYLE}</style>
+</head>
+<body>
+<div id=\"chart\">
+{svg}
+</div>
+<div id=\"subjects\"></div>
+<div id=\"tooltip\" hidden></div>
+<script>
+{SCRIPT}</script>
+</body>
+</html>
+",
+        title = escape(title),
+    )
+}
+
+pub fn save_html(path: impl AsRef<Path>, svg: &SVG, title: &str) -> std::io::Result<()> {
+    std::fs::write(path, render_html(svg, title))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sankey_graph::{Sankey, SankeyStyle};
+
+    #[test]
+    fn html_is_self_contained() {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("A".to_string()), None);
+        let b = sankey.node(None, Some("B".to_string()), None);
+        let edge = sankey.edge(a, b, 2.0, None, None);
+        sankey.add_subject(edge, "10021001".to_string());
+        sankey.add_subject(edge, "10021002".to_string());
+
+        let svg = sankey.draw(400.0, 300.0, SankeyStyle::<fn(f64) -> String>::default());
+        let html = render_html(&svg, "CRS <by dose>");
+
+        assert!(html.contains("<title>CRS &lt;by dose&gt;</title>"));
+        assert!(html.contains("data-subjects=\"10021001 10021002\""));
+        assert!(!html.contains("src=\"http"));
+        assert!(!html.contains("href=\"http"));
+    }
+}
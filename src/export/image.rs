@@ -0,0 +1,170 @@
+use std::path::Path;
+
+use resvg::{
+    tiny_skia::{Color, Pixmap, Transform},
+    usvg::{self, fontdb, PostProcessingSteps, TreeParsing, TreePostProc},
+};
+use svg::node::element::SVG;
+
+/// Font bundled with the crate so that text renders identically regardless of which fonts the
+/// host happens to have installed.
+static FALLBACK_FONT: &[u8] = include_bytes!("../../assets/fonts/DejaVuSans.ttf");
+const FALLBACK_FONT_FAMILY: &str = "DejaVu Sans";
+
+/// SVG user units are CSS pixels, which are defined at 96 dots per inch.
+const CSS_PIXELS_PER_INCH: f32 = 96.0;
+
+#[derive(Debug, thiserror::Error)]
+pub enum ImageExportError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("SVG parsing error: {0}")]
+    Parsing(#[from] usvg::Error),
+
+    #[error("Could not allocate a {0}x{1} pixel canvas")]
+    Canvas(u32, u32),
+
+    #[error("PNG encoding error: {0}")]
+    Encoding(String),
+}
+
+#[derive(Default)]
+pub struct ImageExportOptions {
+    /// Output resolution of raster images, and the physical size of one SVG pixel in PDFs.
+    pub dpi: Option<f32>,
+    /// Fill colour painted behind the diagram. Transparent when `None`.
+    pub background: Option<Color>,
+    /// Also load fonts installed on the host. Off by default so output is reproducible.
+    pub system_fonts: bool,
+}
+
+/// Builds a font database containing the bundled fallback font, with every generic CSS family
+/// (`sans-serif`, `serif`, ...) resolving to it.
+pub fn fallback_font_database(system_fonts: bool) -> fontdb::Database {
+    let mut fontdb = fontdb::Database::new();
+    fontdb.load_font_data(FALLBACK_FONT.to_vec());
+
+    if system_fonts {
+        fontdb.load_system_fonts();
+    }
+
+    fontdb.set_sans_serif_family(FALLBACK_FONT_FAMILY);
+    fontdb.set_serif_family(FALLBACK_FONT_FAMILY);
+    fontdb.set_monospace_family(FALLBACK_FONT_FAMILY);
+    fontdb.set_cursive_family(FALLBACK_FONT_FAMILY);
+    fontdb.set_fantasy_family(FALLBACK_FONT_FAMILY);
+
+    fontdb
+}
+
+fn parse_tree(svg: &SVG, options: &ImageExportOptions) -> Result<usvg::Tree, ImageExportError> {
+    let usvg_options = usvg::Options {
+        font_family: FALLBACK_FONT_FAMILY.to_string(),
+        ..usvg::Options::default()
+    };
+
+    let mut tree = usvg::Tree::from_str(&svg.to_string(), &usvg_options)?;
+
+    let fontdb = fallback_font_database(options.system_fonts);
+    tree.postprocess(PostProcessingSteps::default(), &fontdb);
+
+    Ok(tree)
+}
+
+/// Rasterises `svg` on the CPU and encodes it as a PNG.
+///
+/// The pixel size of the image is the SVG size scaled by `dpi / 96`, so the default of 96 dpi
+/// maps one SVG unit to one pixel.
+pub fn render_png(svg: &SVG, options: &ImageExportOptions) -> Result<Vec<u8>, ImageExportError> {
+    let tree = parse_tree(svg, options)?;
+
+    let scale = options.dpi.unwrap_or(CSS_PIXELS_PER_INCH) / CSS_PIXELS_PER_INCH;
+    let width = (tree.size.width() * scale).ceil() as u32;
+    let height = (tree.size.height() * scale).ceil() as u32;
+
+    let mut pixmap = Pixmap::new(width, height).ok_or(ImageExportError::Canvas(width, height))?;
+
+    if let Some(background) = options.background {
+        pixmap.fill(background);
+    }
+
+    resvg::render(
+        &tree,
+        Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    pixmap
+        .encode_png()
+        .map_err(|err| ImageExportError::Encoding(err.to_string()))
+}
+
+/// Converts `svg` into a single page, vector PDF. Text is embedded as outlines of the bundled
+/// font, so the document does not depend on fonts installed on the reader's machine.
+pub fn render_pdf(svg: &SVG, options: &ImageExportOptions) -> Result<Vec<u8>, ImageExportError> {
+    let tree = parse_tree(svg, options)?;
+
+    let pdf_options = svg2pdf::Options {
+        dpi: options.dpi.unwrap_or(CSS_PIXELS_PER_INCH),
+        ..svg2pdf::Options::default()
+    };
+
+    Ok(svg2pdf::convert_tree(&tree, pdf_options))
+}
+
+pub fn save_png(
+    path: impl AsRef<Path>,
+    svg: &SVG,
+    options: &ImageExportOptions,
+) -> Result<(), ImageExportError> {
+    std::fs::write(path, render_png(svg, options)?)?;
+    Ok(())
+}
+
+pub fn save_pdf(
+    path: impl AsRef<Path>,
+    svg: &SVG,
+    options: &ImageExportOptions,
+) -> Result<(), ImageExportError> {
+    std::fs::write(path, render_pdf(svg, options)?)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sankey_graph::{Sankey, SankeyStyle};
+
+    fn diagram() -> SVG {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("A".to_string()), None);
+        let b = sankey.node(None, Some("B".to_string()), None);
+        let c = sankey.node(None, Some("C".to_string()), None);
+        sankey.edge(a, b, 3.0, None, None);
+        sankey.edge(a, c, 1.0, None, None);
+
+        sankey.draw(400.0, 300.0, SankeyStyle::<fn(f64) -> String>::default())
+    }
+
+    #[test]
+    fn png_size_follows_dpi() {
+        let options = ImageExportOptions {
+            dpi: Some(192.0),
+            background: Some(Color::WHITE),
+            ..ImageExportOptions::default()
+        };
+
+        let png = render_png(&diagram(), &options).unwrap();
+        let pixmap = Pixmap::decode_png(&png).unwrap();
+
+        assert_eq!((pixmap.width(), pixmap.height()), (800, 600));
+    }
+
+    #[test]
+    fn pdf_has_header() {
+        let pdf = render_pdf(&diagram(), &ImageExportOptions::default()).unwrap();
+
+        assert!(pdf.starts_with(b"%PDF-"));
+    }
+}
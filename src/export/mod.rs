@@ -0,0 +1,4 @@
+pub mod dot;
+pub mod html;
+pub mod image;
+pub mod web;
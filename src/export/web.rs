@@ -0,0 +1,349 @@
+use serde::{Deserialize, Serialize};
+
+use crate::sankey_graph::{Sankey, SankeyNodeID};
+
+#[derive(Debug, thiserror::Error)]
+pub enum WebImportError {
+    #[error("Link {0} refers to node {1}, but there are only {2} nodes")]
+    UnknownNode(usize, usize, usize),
+
+    #[error("The `{0}` array has {1} entries, expected {2}")]
+    LengthMismatch(&'static str, usize, usize),
+}
+
+fn lookup_node(
+    nodes: &[SankeyNodeID],
+    link: usize,
+    node: usize,
+) -> Result<SankeyNodeID, WebImportError> {
+    nodes
+        .get(node)
+        .copied()
+        .ok_or(WebImportError::UnknownNode(link, node, nodes.len()))
+}
+
+fn check_length<T>(name: &'static str, array: &[T], expected: usize) -> Result<(), WebImportError> {
+    match array.len() == expected {
+        true => Ok(()),
+        false => Err(WebImportError::LengthMismatch(name, array.len(), expected)),
+    }
+}
+
+fn all_none<T>(values: &[Option<T>]) -> bool {
+    values.iter().all(Option::is_none)
+}
+
+/// A Plotly `sankey` trace, i.e. one entry of a figure's `data` array.
+///
+/// Attributes are stored column-wise, as Plotly expects. Missing labels are written as empty
+/// strings and colour arrays are omitted entirely when no element has a colour.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PlotlySankeyTrace {
+    #[serde(rename = "type")]
+    pub trace_type: String,
+    pub orientation: String,
+    pub node: PlotlyNodes,
+    pub link: PlotlyLinks,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PlotlyNodes {
+    pub label: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Vec<Option<String>>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct PlotlyLinks {
+    pub source: Vec<usize>,
+    pub target: Vec<usize>,
+    pub value: Vec<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<Vec<String>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<Vec<Option<String>>>,
+    /// Subject ids of each link, available to Plotly hover templates as `%{customdata}`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub customdata: Option<Vec<Vec<String>>>,
+}
+
+impl From<&Sankey> for PlotlySankeyTrace {
+    fn from(sankey: &Sankey) -> Self {
+        let node_colors: Vec<Option<String>> = sankey
+            .node_ids()
+            .map(|node| sankey.node_color(node).map(str::to_string))
+            .collect();
+        let link_labels: Vec<Option<String>> = sankey
+            .edge_ids()
+            .map(|edge| sankey.edge_label(edge).map(str::to_string))
+            .collect();
+        let link_colors: Vec<Option<String>> = sankey
+            .edge_ids()
+            .map(|edge| sankey.edge_color(edge).map(str::to_string))
+            .collect();
+        let link_subjects: Vec<Vec<String>> = sankey
+            .edge_ids()
+            .map(|edge| sankey.subjects(edge).to_vec())
+            .collect();
+
+        PlotlySankeyTrace {
+            trace_type: "sankey".to_string(),
+            orientation: "h".to_string(),
+            node: PlotlyNodes {
+                label: sankey
+                    .node_ids()
+                    .map(|node| sankey.node_label(node).unwrap_or_default().to_string())
+                    .collect(),
+                color: (!all_none(&node_colors)).then_some(node_colors),
+            },
+            link: PlotlyLinks {
+                source: sankey
+                    .edge_ids()
+                    .map(|edge| sankey.source(edge).index())
+                    .collect(),
+                target: sankey
+                    .edge_ids()
+                    .map(|edge| sankey.target(edge).index())
+                    .collect(),
+                value: sankey
+                    .edge_ids()
+                    .map(|edge| sankey.edge_value(edge))
+                    .collect(),
+                label: (!all_none(&link_labels)).then(|| {
+                    link_labels
+                        .into_iter()
+                        .map(Option::unwrap_or_default)
+                        .collect()
+                }),
+                color: (!all_none(&link_colors)).then_some(link_colors),
+                customdata: link_subjects
+                    .iter()
+                    .any(|subjects| !subjects.is_empty())
+                    .then_some(link_subjects),
+            },
+        }
+    }
+}
+
+impl TryFrom<&PlotlySankeyTrace> for Sankey {
+    type Error = WebImportError;
+
+    fn try_from(trace: &PlotlySankeyTrace) -> Result<Self, Self::Error> {
+        let node_count = trace.node.label.len();
+        let link_count = trace.link.source.len();
+
+        check_length("link.target", &trace.link.target, link_count)?;
+        check_length("link.value", &trace.link.value, link_count)?;
+        if let Some(color) = &trace.node.color {
+            check_length("node.color", color, node_count)?;
+        }
+        if let Some(label) = &trace.link.label {
+            check_length("link.label", label, link_count)?;
+        }
+        if let Some(color) = &trace.link.color {
+            check_length("link.color", color, link_count)?;
+        }
+        if let Some(customdata) = &trace.link.customdata {
+            check_length("link.customdata", customdata, link_count)?;
+        }
+
+        let mut sankey = Sankey::new();
+
+        let nodes: Vec<SankeyNodeID> = (0..node_count)
+            .map(|i| {
+                let label = &trace.node.label[i];
+                let color = trace.node.color.as_ref().and_then(|c| c[i].clone());
+                sankey.node(None, (!label.is_empty()).then(|| label.clone()), color)
+            })
+            .collect();
+
+        for i in 0..link_count {
+            let source = lookup_node(&nodes, i, trace.link.source[i])?;
+            let target = lookup_node(&nodes, i, trace.link.target[i])?;
+            let label = trace
+                .link
+                .label
+                .as_ref()
+                .map(|l| l[i].clone())
+                .filter(|l| !l.is_empty());
+            let color = trace.link.color.as_ref().and_then(|c| c[i].clone());
+
+            let edge = sankey.edge(source, target, trace.link.value[i], label, color);
+            if let Some(customdata) = &trace.link.customdata {
+                for subject in &customdata[i] {
+                    sankey.add_subject(edge, subject.clone());
+                }
+            }
+        }
+
+        Ok(sankey)
+    }
+}
+
+/// The `{nodes, links}` graph consumed by `d3-sankey`, with links referring to nodes by index.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct D3SankeyGraph {
+    pub nodes: Vec<D3SankeyNode>,
+    pub links: Vec<D3SankeyLink>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct D3SankeyNode {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    /// d3-sankey uses `fixedValue` to override the value computed from a node's links.
+    #[serde(
+        default,
+        rename = "fixedValue",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub fixed_value: Option<f64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub struct D3SankeyLink {
+    pub source: usize,
+    pub target: usize,
+    pub value: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub subjects: Vec<String>,
+}
+
+impl From<&Sankey> for D3SankeyGraph {
+    fn from(sankey: &Sankey) -> Self {
+        D3SankeyGraph {
+            nodes: sankey
+                .node_ids()
+                .map(|node| D3SankeyNode {
+                    name: sankey.node_label(node).map(str::to_string),
+                    color: sankey.node_color(node).map(str::to_string),
+                    fixed_value: sankey.value(node),
+                })
+                .collect(),
+            links: sankey
+                .edge_ids()
+                .map(|edge| D3SankeyLink {
+                    source: sankey.source(edge).index(),
+                    target: sankey.target(edge).index(),
+                    value: sankey.edge_value(edge),
+                    label: sankey.edge_label(edge).map(str::to_string),
+                    color: sankey.edge_color(edge).map(str::to_string),
+                    subjects: sankey.subjects(edge).to_vec(),
+                })
+                .collect(),
+        }
+    }
+}
+
+impl TryFrom<&D3SankeyGraph> for Sankey {
+    type Error = WebImportError;
+
+    fn try_from(graph: &D3SankeyGraph) -> Result<Self, Self::Error> {
+        let mut sankey = Sankey::new();
+
+        let nodes: Vec<SankeyNodeID> = graph
+            .nodes
+            .iter()
+            .map(|node| sankey.node(node.fixed_value, node.name.clone(), node.color.clone()))
+            .collect();
+
+        for (i, link) in graph.links.iter().enumerate() {
+            let source = lookup_node(&nodes, i, link.source)?;
+            let target = lookup_node(&nodes, i, link.target)?;
+
+            let edge = sankey.edge(
+                source,
+                target,
+                link.value,
+                link.label.clone(),
+                link.color.clone(),
+            );
+            for subject in &link.subjects {
+                sankey.add_subject(edge, subject.clone());
+            }
+        }
+
+        Ok(sankey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static PLOTLY_FIXTURE: &str = include_str!("../../tests/fixtures/plotly_sankey.json");
+    static D3_FIXTURE: &str = include_str!("../../tests/fixtures/d3_sankey.json");
+
+    fn sankey() -> Sankey {
+        let mut sankey = Sankey::new();
+        let d1 = sankey.node(
+            None,
+            Some("(G0, D1)".to_string()),
+            Some("#1b9e77".to_string()),
+        );
+        let g0 = sankey.node(
+            None,
+            Some("(G0, D2)".to_string()),
+            Some("#1b9e77".to_string()),
+        );
+        let g1 = sankey.node(Some(2.0), Some("(G1, D2)".to_string()), None);
+
+        let edge = sankey.edge(d1, g0, 2.0, None, None);
+        sankey.add_subject(edge, "10021001".to_string());
+        sankey.add_subject(edge, "10021002".to_string());
+        let edge = sankey.edge(d1, g1, 1.0, Some("escalation".to_string()), None);
+        sankey.add_subject(edge, "10021003".to_string());
+
+        sankey
+    }
+
+    #[test]
+    fn plotly_round_trip() {
+        let fixture: serde_json::Value = serde_json::from_str(PLOTLY_FIXTURE).unwrap();
+
+        let exported = serde_json::to_value(PlotlySankeyTrace::from(&sankey())).unwrap();
+        assert_eq!(exported, fixture);
+
+        let trace: PlotlySankeyTrace = serde_json::from_str(PLOTLY_FIXTURE).unwrap();
+        let imported = Sankey::try_from(&trace).unwrap();
+        assert_eq!(PlotlySankeyTrace::from(&imported), trace);
+    }
+
+    #[test]
+    fn d3_round_trip() {
+        let fixture: serde_json::Value = serde_json::from_str(D3_FIXTURE).unwrap();
+
+        let exported = serde_json::to_value(D3SankeyGraph::from(&sankey())).unwrap();
+        assert_eq!(exported, fixture);
+
+        let graph: D3SankeyGraph = serde_json::from_str(D3_FIXTURE).unwrap();
+        let imported = Sankey::try_from(&graph).unwrap();
+        assert_eq!(D3SankeyGraph::from(&imported), graph);
+    }
+
+    #[test]
+    fn unknown_node_is_rejected() {
+        let graph = D3SankeyGraph {
+            nodes: vec![],
+            links: vec![D3SankeyLink {
+                source: 0,
+                target: 1,
+                value: 1.0,
+                label: None,
+                color: None,
+                subjects: vec![],
+            }],
+        };
+
+        assert!(matches!(
+            Sankey::try_from(&graph),
+            Err(WebImportError::UnknownNode(0, 0, 0))
+        ));
+    }
+}
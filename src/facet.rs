@@ -0,0 +1,268 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use svg::{
+    node::{
+        self,
+        element::{Rectangle, Style, Text, SVG},
+    },
+    Node,
+};
+
+use crate::sankey_graph::{self, Sankey, SankeyOrientation, SankeyStyle};
+
+/// Splits records into groups sharing the same key, ordered by key.
+pub fn partition<R: Clone, K: Ord>(records: &[R], key: impl Fn(&R) -> K) -> BTreeMap<K, Vec<R>> {
+    let mut groups = BTreeMap::<K, Vec<R>>::new();
+
+    for record in records {
+        groups.entry(key(record)).or_default().push(record.clone());
+    }
+
+    groups
+}
+
+pub struct Facet {
+    title: String,
+    sankey: Sankey,
+}
+
+/// Small multiples: one Sankey per subgroup, laid out in a grid and drawn at a common scale so
+/// that ribbon widths can be compared between facets.
+#[derive(Default)]
+pub struct FacetGrid {
+    facets: Vec<Facet>,
+}
+
+impl FacetGrid {
+    pub fn new() -> FacetGrid {
+        FacetGrid::default()
+    }
+
+    /// Partitions `records` by `key` and builds one facet per group, titled with the key.
+    pub fn from_records<R: Clone, K: Ord + Display, E>(
+        records: &[R],
+        key: impl Fn(&R) -> K,
+        build: impl Fn(&[R]) -> Result<Sankey, E>,
+    ) -> Result<FacetGrid, E> {
+        let mut grid = FacetGrid::new();
+
+        for (key, group) in partition(records, key) {
+            grid.facet(key.to_string(), build(&group)?);
+        }
+
+        Ok(grid)
+    }
+
+    pub fn facet(&mut self, title: String, sankey: Sankey) {
+        self.facets.push(Facet { title, sankey });
+    }
+
+    pub fn len(&self) -> usize {
+        self.facets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.facets.is_empty()
+    }
+
+    /// Largest scale, in pixels per unit of flow, at which every facet fits in its cell.
+    pub fn shared_scale<F: Fn(f64) -> String>(
+        &self,
+        facet_width: f64,
+        facet_height: f64,
+        style: &SankeyStyle<F>,
+    ) -> f64 {
+        sankey_graph::shared_scale(
+            self.facets.iter().map(|facet| &facet.sankey),
+            facet_width,
+            facet_height,
+            style,
+        )
+    }
+
+    /// Draws the facets `columns` to a row, each in a `facet_width` by `facet_height` cell below
+    /// its title.
+    pub fn draw<F: Fn(f64) -> String + Clone>(
+        &self,
+        columns: usize,
+        facet_width: f64,
+        facet_height: f64,
+        style: SankeyStyle<F>,
+    ) -> SVG {
+        // Titles are set like the panels' text, at the size the panels use by default
+        let theme = style.theme.clone().unwrap_or_default();
+        let (_, breadth) = style
+            .orientation
+            .unwrap_or(SankeyOrientation::LeftToRight)
+            .flow_size(facet_width, facet_height);
+        let font_family: String = style
+            .font_family
+            .clone()
+            .unwrap_or(theme.font_family.clone());
+        let font_size: f64 = style.font_size.unwrap_or(breadth / 50.0);
+        let font_color: String = style.font_color.clone().unwrap_or(theme.font_color.clone());
+
+        let columns = columns.clamp(1, self.facets.len().max(1));
+        let rows = self.facets.len().div_ceil(columns);
+        let title_height = font_size * 3.0;
+        let width = columns as f64 * facet_width;
+        let height = rows as f64 * (title_height + facet_height);
+
+        let style = SankeyStyle {
+            scale: Some(style.scale.unwrap_or(self.shared_scale(
+                facet_width,
+                facet_height,
+                &style,
+            ))),
+            ..style
+        };
+
+        // Initialise SVG
+
+        let mut document = SVG::new();
+
+        document.assign("viewBox", (0.0, 0.0, width, height));
+
+        document.append(Style::new(format!(
+            "text.facet {{
+	fill: {font_color};
+	text-anchor: middle;
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {}px;
+	font-weight: bold;
+}}",
+            font_size * 1.5
+        )));
+
+        // The panels only fill their own cells, so the titles need the background too
+        if let Some(background) = theme.background.as_deref() {
+            let mut rect = Rectangle::new();
+            rect.assign("x", 0.0);
+            rect.assign("y", 0.0);
+            rect.assign("width", width);
+            rect.assign("height", height);
+            rect.assign("class", "background");
+            rect.assign("style", format!("fill:{background}"));
+            document.append(rect);
+        }
+
+        // Generate facets
+
+        for (index, facet) in self.facets.iter().enumerate() {
+            let x = (index % columns) as f64 * facet_width;
+            let y = (index / columns) as f64 * (title_height + facet_height);
+
+            let mut title = Text::new();
+            title.assign("x", x + facet_width / 2.0);
+            title.assign("y", y + title_height / 2.0);
+            title.assign("class", "facet");
+            title.append(node::Text::new(&facet.title));
+
+            // Each facet gets its own ids, as the nested SVGs share the document's
+            let mut sankey = facet.sankey.draw(
+                facet_width,
+                facet_height,
+                SankeyStyle {
+                    id_prefix: Some(format!(
+                        "{}facet-{}-",
+                        style.id_prefix.as_deref().unwrap_or_default(),
+                        index + 1
+                    )),
+                    ..style.clone()
+                },
+            );
+            sankey.assign("x", x);
+            sankey.assign("y", y + title_height);
+            sankey.assign("width", facet_width);
+            sankey.assign("height", facet_height);
+            sankey.assign("data-facet", facet.title.as_str());
+
+            // Add to SVG
+
+            document.append(title);
+            document.append(sankey);
+        }
+
+        document
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::theme::Theme;
+
+    fn chain(values: &[f64]) -> Result<Sankey, ()> {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("a".to_string()), None);
+        let b = sankey.node(None, Some("b".to_string()), None);
+        for &value in values {
+            sankey.edge(a, b, value, None, None);
+        }
+        Ok(sankey)
+    }
+
+    #[test]
+    fn facets_share_one_scale() {
+        let records = vec![("arm 2", 1.0), ("arm 1", 1.0), ("arm 2", 3.0)];
+
+        let groups = partition(&records, |&(arm, _)| arm);
+        assert_eq!(
+            groups.keys().copied().collect::<Vec<_>>(),
+            ["arm 1", "arm 2"]
+        );
+
+        let grid = FacetGrid::from_records(
+            &records,
+            |&(arm, _)| arm,
+            |group| chain(&group.iter().map(|&(_, v)| v).collect::<Vec<_>>()),
+        )
+        .unwrap();
+        assert_eq!(grid.len(), 2);
+
+        let style = SankeyStyle::<fn(f64) -> String> {
+            border: Some(0.0),
+            ..SankeyStyle::default()
+        };
+
+        // The larger facet (a total flow of 4) limits the scale of both
+        assert_eq!(grid.shared_scale(200.0, 100.0, &style), 25.0);
+
+        let svg = grid.draw(2, 200.0, 100.0, style).to_string();
+        assert!(svg.contains("data-facet=\"arm 1\""));
+        // The smaller facet is drawn a quarter of the height of the larger one
+        assert!(svg.contains("height=\"25\""));
+
+        // Both facets draw the same nodes, but under different ids
+        let ids: Vec<&str> = svg
+            .split(" id=\"")
+            .skip(1)
+            .map(|rest| &rest[..rest.find('"').unwrap()])
+            .collect();
+        let unique: std::collections::BTreeSet<&str> = ids.iter().copied().collect();
+        assert!(ids.contains(&"facet-1-node-0") && ids.contains(&"facet-2-node-0"));
+        assert_eq!(ids.len(), unique.len());
+
+        // Titles follow the theme of the panels
+        let svg = grid
+            .draw(
+                2,
+                200.0,
+                100.0,
+                SankeyStyle::<fn(f64) -> String> {
+                    theme: Some(Theme {
+                        font_family: "serif".to_string(),
+                        ..Theme::dark()
+                    }),
+                    ..SankeyStyle::default()
+                },
+            )
+            .to_string();
+        let title_style = &svg[svg.find("text.facet").unwrap()..];
+        assert!(title_style.contains("fill: #EEE"));
+        assert!(title_style.contains("font-family: serif"));
+        assert!(svg.contains("style=\"fill:#1E1E1E\""));
+    }
+}
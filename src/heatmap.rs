@@ -0,0 +1,484 @@
+use std::fmt::Display;
+
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Directed;
+use svg::{
+    node::{
+        self,
+        element::{Group, Rectangle, Style, Text, SVG},
+    },
+    Node,
+};
+
+use crate::palette::Rgb;
+use crate::sankey_graph::SankeyStyle;
+use crate::theme::Theme;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HeatmapValue {
+    // Number of transitions from the row state to the column state.
+    Count,
+    // Share of the row's transitions that go to the column state, so that every row sums to 100.
+    RowPercentage,
+}
+
+pub struct HeatmapStyle<F: Fn(f64) -> String> {
+    pub number_format: Option<F>,
+    pub value: Option<HeatmapValue>,
+    pub color: Option<String>,
+    pub cell_separation: Option<f64>,
+    pub font_family: Option<String>,
+    pub font_size: Option<f64>,
+    pub font_color: Option<String>,
+    pub border: Option<f64>,
+    /// Colours and fonts. The settings above take precedence over the theme's, and cells are
+    /// filled with its node colour.
+    pub theme: Option<Theme>,
+}
+
+impl<F: Fn(f64) -> String> Default for HeatmapStyle<F> {
+    fn default() -> Self {
+        HeatmapStyle {
+            number_format: None,
+            value: None,
+            color: None,
+            cell_separation: None,
+            font_family: None,
+            font_size: None,
+            font_color: None,
+            border: None,
+            theme: None,
+        }
+    }
+}
+
+/// Uses the fonts, colours and number format of a Sankey, so that a heatmap can sit next to it.
+/// Cells take the colour of the highest level of the Sankey's ordinal scale, if it has one.
+impl<F: Fn(f64) -> String> From<SankeyStyle<F>> for HeatmapStyle<F> {
+    fn from(style: SankeyStyle<F>) -> Self {
+        HeatmapStyle {
+            number_format: style.number_format,
+            color: style.ordinal.map(|scale| {
+                scale
+                    .level_color(scale.levels.len().saturating_sub(1))
+                    .to_string()
+            }),
+            font_family: style.font_family,
+            font_size: style.font_size,
+            font_color: style.font_color,
+            border: style.border,
+            theme: style.theme,
+            ..HeatmapStyle::default()
+        }
+    }
+}
+
+/// Counts of transitions from the states of one layer (rows) to the states of the next (columns).
+pub struct TransitionMatrix {
+    title: Option<String>,
+    row_labels: Vec<String>,
+    column_labels: Vec<String>,
+    counts: Vec<Vec<f64>>,
+}
+
+impl TransitionMatrix {
+    pub fn new(row_labels: Vec<String>, column_labels: Vec<String>) -> TransitionMatrix {
+        let counts = vec![vec![0.0; column_labels.len()]; row_labels.len()];
+        TransitionMatrix {
+            title: None,
+            row_labels,
+            column_labels,
+            counts,
+        }
+    }
+
+    pub fn set_title(&mut self, title: String) {
+        self.title = Some(title);
+    }
+
+    pub fn add(&mut self, row: usize, column: usize, count: f64) {
+        self.counts[row][column] += count;
+    }
+
+    pub fn count(&self, row: usize, column: usize) -> f64 {
+        self.counts[row][column]
+    }
+
+    pub fn row_total(&self, row: usize) -> f64 {
+        self.counts[row].iter().sum()
+    }
+
+    pub fn value(&self, row: usize, column: usize, value: HeatmapValue) -> f64 {
+        match value {
+            HeatmapValue::Count => self.count(row, column),
+            HeatmapValue::RowPercentage => match self.row_total(row) > 0.0 {
+                true => self.count(row, column) / self.row_total(row) * 100.0,
+                false => 0.0,
+            },
+        }
+    }
+
+    fn max_value(&self, value: HeatmapValue) -> f64 {
+        (0..self.row_labels.len())
+            .flat_map(|row| (0..self.column_labels.len()).map(move |column| (row, column)))
+            .map(|(row, column)| self.value(row, column, value))
+            .fold(0.0, f64::max)
+    }
+
+    pub fn draw<F: Fn(f64) -> String>(
+        &self,
+        width: f64,
+        height: f64,
+        style: HeatmapStyle<F>,
+    ) -> SVG {
+        draw_small_multiples(std::slice::from_ref(self), 1, width, height, style)
+    }
+
+    fn draw_cells<F: Fn(f64) -> String>(
+        &self,
+        (x, y, width, height): (f64, f64, f64, f64),
+        max_value: f64,
+        style: &ResolvedStyle<F>,
+    ) -> Group {
+        let mut group = Group::new();
+        group.assign("class", "matrix");
+
+        // Leave room for the title above and the row labels to the left
+        let label_space = style.font_size * 4.0;
+        let grid_x = x + label_space;
+        let grid_y = y + style.font_size * 4.0;
+        let grid_width = width - label_space;
+        let grid_height = height - style.font_size * 4.0;
+
+        let columns = self.column_labels.len().max(1) as f64;
+        let rows = self.row_labels.len().max(1) as f64;
+        let cell_width = (grid_width - style.cell_separation * (columns - 1.0)) / columns;
+        let cell_height = (grid_height - style.cell_separation * (rows - 1.0)) / rows;
+
+        if let Some(title) = &self.title {
+            let mut text = Text::new();
+            text.assign("x", grid_x + grid_width / 2.0);
+            text.assign("y", y + style.font_size);
+            text.assign("class", "title");
+            text.append(node::Text::new(title));
+            group.append(text);
+        }
+
+        for (column, label) in self.column_labels.iter().enumerate() {
+            let mut text = Text::new();
+            text.assign(
+                "x",
+                grid_x + column as f64 * (cell_width + style.cell_separation) + cell_width / 2.0,
+            );
+            text.assign("y", grid_y - style.font_size);
+            text.assign("class", "column");
+            text.append(node::Text::new(label));
+            group.append(text);
+        }
+
+        for (row, label) in self.row_labels.iter().enumerate() {
+            let cell_y = grid_y + row as f64 * (cell_height + style.cell_separation);
+
+            let mut text = Text::new();
+            text.assign("x", grid_x - style.font_size / 2.0);
+            text.assign("y", cell_y + cell_height / 2.0);
+            text.assign("class", "row");
+            text.append(node::Text::new(label));
+            group.append(text);
+
+            for column in 0..self.column_labels.len() {
+                let cell_x = grid_x + column as f64 * (cell_width + style.cell_separation);
+                let value = self.value(row, column, style.value);
+                let opacity = match max_value > 0.0 {
+                    true => value / max_value,
+                    false => 0.0,
+                };
+
+                let mut rect = Rectangle::new();
+                rect.assign("x", cell_x);
+                rect.assign("y", cell_y);
+                rect.assign("width", cell_width);
+                rect.assign("height", cell_height);
+                rect.assign("class", "cell");
+                rect.assign("data-row", row);
+                rect.assign("data-column", column);
+                rect.assign("data-count", self.count(row, column));
+                rect.assign("style", format!("fill-opacity:{opacity:.3}"));
+                group.append(rect);
+
+                let number = match style.value {
+                    HeatmapValue::Count => style
+                        .number_format
+                        .as_ref()
+                        .map_or(value.to_string(), |f| f(value)),
+                    HeatmapValue::RowPercentage => format!("{value:.0}%"),
+                };
+
+                let mut text = Text::new();
+                text.assign("x", cell_x + cell_width / 2.0);
+                text.assign("y", cell_y + cell_height / 2.0);
+                // Keep annotations readable on the darkest cells
+                text.assign(
+                    "class",
+                    match opacity > 0.5 {
+                        true => "cell dark",
+                        false => "cell",
+                    },
+                );
+                text.append(node::Text::new(number));
+                group.append(text);
+            }
+        }
+
+        group
+    }
+}
+
+struct ResolvedStyle<F: Fn(f64) -> String> {
+    number_format: Option<F>,
+    value: HeatmapValue,
+    cell_separation: f64,
+    font_size: f64,
+}
+
+/// Draws several matrices side by side, `columns` to a row, on a common colour scale.
+pub fn draw_small_multiples<F: Fn(f64) -> String>(
+    matrices: &[TransitionMatrix],
+    columns: usize,
+    width: f64,
+    height: f64,
+    style: HeatmapStyle<F>,
+) -> SVG {
+    let theme = style.theme.unwrap_or_default();
+    let value = style.value.unwrap_or(HeatmapValue::Count);
+    let color: String = style.color.unwrap_or(theme.node_fill.clone());
+    let cell_separation = style.cell_separation.unwrap_or(height / 200.0);
+    let font_family: String = style.font_family.unwrap_or(theme.font_family.clone());
+    let font_size: f64 = style.font_size.unwrap_or(height / 50.0);
+    let font_color: String = style.font_color.unwrap_or(theme.font_color.clone());
+    // Annotations on the most filled cells contrast with the cell colour rather than the page
+    let dark_cell_font_color = match Rgb::from_hex(&color) {
+        Ok(rgb) if rgb.to_oklab()[0] > 0.6 => "#000",
+        _ => "#FFF",
+    };
+    let border: f64 = style.border.unwrap_or(height / 10.0);
+
+    let resolved = ResolvedStyle {
+        number_format: style.number_format,
+        value,
+        cell_separation,
+        font_size,
+    };
+
+    // Initialise SVG
+
+    let mut document = SVG::new();
+
+    document.assign("viewBox", (0.0, 0.0, width, height));
+
+    document.append(Style::new(format!(
+        "rect.cell {{
+	fill: {color};
+}}
+
+text {{
+	fill: {font_color};
+	text-anchor: middle;
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}
+
+text.row {{
+	text-anchor: end;
+}}
+
+text.cell.dark {{
+	fill: {dark_cell_font_color};
+}}"
+    )));
+
+    if let Some(background) = theme.background.as_deref() {
+        let mut rect = Rectangle::new();
+        rect.assign("x", 0.0);
+        rect.assign("y", 0.0);
+        rect.assign("width", width);
+        rect.assign("height", height);
+        rect.assign("class", "background");
+        rect.assign("style", format!("fill:{background}"));
+        document.append(rect);
+    }
+
+    // Generate matrices
+
+    let max_value = matrices
+        .iter()
+        .map(|matrix| matrix.max_value(value))
+        .fold(0.0, f64::max);
+
+    let columns = columns.clamp(1, matrices.len().max(1));
+    let rows = matrices.len().div_ceil(columns).max(1);
+    let panel_width = (width - border * 2.0) / columns as f64;
+    let panel_height = (height - border * 2.0) / rows as f64;
+
+    for (index, matrix) in matrices.iter().enumerate() {
+        let x = border + (index % columns) as f64 * panel_width;
+        let y = border + (index / columns) as f64 * panel_height;
+        // Gutter between neighbouring panels
+        let gutter = match matrices.len() > 1 {
+            true => font_size * 2.0,
+            false => 0.0,
+        };
+
+        document.append(matrix.draw_cells(
+            (x, y, panel_width - gutter, panel_height - gutter),
+            max_value,
+            &resolved,
+        ));
+    }
+
+    document
+}
+
+/// Builds the matrix of transitions from the nodes whose step is `from` to the nodes whose step is
+/// `to`, e.g. from one dose to the next.
+pub fn transition_matrix<N: Clone + Display, E: Clone, S: PartialEq>(
+    graph: &Graph<N, E, Directed>,
+    node_step: &dyn Fn(&N) -> S,
+    from: &S,
+    to: &S,
+    node_labeller: &dyn Fn(N) -> String,
+    edge_weight: &dyn Fn(&E) -> f64,
+) -> TransitionMatrix {
+    let step_nodes = |step: &S| -> Vec<NodeIndex> {
+        graph
+            .node_indices()
+            .filter(|&node| node_step(&graph[node]) == *step)
+            .collect()
+    };
+    let rows = step_nodes(from);
+    let columns = step_nodes(to);
+
+    let mut matrix = TransitionMatrix::new(
+        rows.iter()
+            .map(|&n| node_labeller(graph[n].clone()))
+            .collect(),
+        columns
+            .iter()
+            .map(|&n| node_labeller(graph[n].clone()))
+            .collect(),
+    );
+
+    for edge in graph.edge_references() {
+        let row = rows.iter().position(|&n| n == edge.source());
+        let column = columns.iter().position(|&n| n == edge.target());
+        if let (Some(row), Some(column)) = (row, column) {
+            matrix.add(row, column, edge_weight(edge.weight()));
+        }
+    }
+
+    matrix
+}
+
+/// Builds one matrix per pair of consecutive steps, titled with the steps, e.g. `D1 → D2`.
+/// Subjects are counted where their step puts them, so one entering at a later dose still lands
+/// in the matrix for that dose.
+pub fn transition_matrices<N: Clone + Display, E: Clone, S: Ord + Display>(
+    graph: &Graph<N, E, Directed>,
+    node_step: &dyn Fn(&N) -> S,
+    node_labeller: &dyn Fn(N) -> String,
+    edge_weight: &dyn Fn(&E) -> f64,
+) -> Vec<TransitionMatrix> {
+    let mut steps: Vec<S> = graph.node_weights().map(node_step).collect();
+    steps.sort();
+    steps.dedup();
+
+    steps
+        .windows(2)
+        .map(|pair| {
+            let (from, to) = (&pair[0], &pair[1]);
+            let mut matrix =
+                transition_matrix(graph, node_step, from, to, node_labeller, edge_weight);
+            matrix.set_title(format!("{from} → {to}"));
+            matrix
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::CytokineReleaseSyndromeGrade;
+
+    #[test]
+    fn counts_transitions_between_steps() {
+        let mut graph = Graph::<&str, f64, Directed>::new();
+        let a1 = graph.add_node("a1");
+        let b1 = graph.add_node("b1");
+        let a2 = graph.add_node("a2");
+        let b2 = graph.add_node("b2");
+        // Enters at the second step, so it must not be counted with the first
+        let c2 = graph.add_node("c2");
+        let a3 = graph.add_node("a3");
+        graph.add_edge(a1, a2, 1.0);
+        graph.add_edge(a1, a2, 1.0);
+        graph.add_edge(a1, b2, 2.0);
+        graph.add_edge(b1, b2, 1.0);
+        graph.add_edge(b2, a3, 1.0);
+        graph.add_edge(c2, a3, 1.0);
+
+        let matrices = transition_matrices(
+            &graph,
+            &|n| format!("D{}", &n[1..]),
+            &|n| n.to_string(),
+            &|&w| w,
+        );
+
+        assert_eq!(matrices.len(), 2);
+        let first = &matrices[0];
+        assert_eq!(first.row_labels, vec!["a1", "b1"]);
+        assert_eq!(first.column_labels, vec!["a2", "b2", "c2"]);
+        assert_eq!(first.counts, vec![vec![2.0, 2.0, 0.0], vec![0.0, 1.0, 0.0]]);
+        assert_eq!(first.value(0, 1, HeatmapValue::RowPercentage), 50.0);
+        assert_eq!(first.value(1, 1, HeatmapValue::RowPercentage), 100.0);
+        assert_eq!(matrices[1].row_labels, vec!["a2", "b2", "c2"]);
+        assert_eq!(matrices[1].counts, vec![vec![0.0], vec![1.0], vec![1.0]]);
+
+        let svg = draw_small_multiples(
+            &matrices,
+            2,
+            800.0,
+            400.0,
+            HeatmapStyle::<fn(f64) -> String> {
+                value: Some(HeatmapValue::RowPercentage),
+                ..HeatmapStyle::default()
+            },
+        )
+        .to_string();
+
+        assert_eq!(svg.matches("<rect").count(), 6 + 3);
+        assert!(svg.contains("50%"));
+        assert!(svg.contains("D1 → D2"));
+        assert!(svg.contains("D2 → D3"));
+
+        // Themes and grade colours carry over from the Sankey
+        let svg = draw_small_multiples(
+            &matrices,
+            2,
+            800.0,
+            400.0,
+            HeatmapStyle::from(SankeyStyle::<fn(f64) -> String> {
+                theme: Some(Theme::dark()),
+                ordinal: Some(CytokineReleaseSyndromeGrade::ordinal_scale()),
+                ..SankeyStyle::default()
+            }),
+        )
+        .to_string();
+
+        assert!(svg.contains("fill: #800026"));
+        assert!(svg.contains("fill: #EEE"));
+        assert!(svg.contains("text.cell.dark {\n\tfill: #FFF;"));
+        assert!(svg.contains("class=\"background\""));
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use fdg_sim::{
+    force::{self, Force},
+    glam::Vec3,
+    ForceGraph, ForceGraphHelper, Simulation, SimulationParameters,
+};
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::{Directed, Undirected};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use crate::sankey::SankeyLayers;
+
+/// Position of every node of a graph, in abstract units with `y` pointing down.
+pub type NodeLocations = BTreeMap<NodeIndex, (f64, f64)>;
+
+pub const LAYER_SPACING: f64 = 200.0;
+pub const NODE_SPACING: f64 = 80.0;
+
+/// Places nodes in columns by layer, so that the graph reads left to right like the Sankey.
+/// Nodes that no layer was found for, such as those on a cycle, share a final column.
+pub fn layered_locations<N: Clone + Display, E: Clone>(
+    graph: &Graph<N, E, Directed>,
+) -> NodeLocations {
+    let mut locations = NodeLocations::new();
+
+    let layers = SankeyLayers::new(graph).collect_by_layer();
+    let leftover_layer = layers.keys().max().map_or(0, |&layer| layer + 1);
+
+    for (layer, mut nodes) in layers {
+        nodes.sort();
+        for (slot, node) in nodes.into_iter().enumerate() {
+            locations.insert(
+                node,
+                (layer as f64 * LAYER_SPACING, slot as f64 * NODE_SPACING),
+            );
+        }
+    }
+
+    let leftovers: Vec<NodeIndex> = graph
+        .node_indices()
+        .filter(|node| !locations.contains_key(node))
+        .collect();
+    for (slot, node) in leftovers.into_iter().enumerate() {
+        locations.insert(
+            node,
+            (
+                leftover_layer as f64 * LAYER_SPACING,
+                slot as f64 * NODE_SPACING,
+            ),
+        );
+    }
+
+    locations
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ForceKind {
+    // Fruchterman & Reingold (1991): repulsion between all nodes, attraction along edges.
+    FruchtermanReingold,
+    // fdg-sim's variant of the above, optionally pulling nodes towards the origin and
+    // re-centering the graph every step.
+    Handy { gravity: bool, centering: bool },
+}
+
+#[derive(Clone, Debug)]
+pub struct ForceLayoutSettings {
+    pub force: ForceKind,
+    pub iterations: usize,
+    /// Length of one simulation step.
+    pub dt: f32,
+    /// Ideal distance between connected nodes.
+    pub scale: f32,
+    /// Fraction of velocity kept between steps; lower values settle faster.
+    pub cooloff_factor: f32,
+    /// Width of the square the nodes are randomly placed in before the first step.
+    pub node_start_size: f32,
+    /// Seed of that random placement, so that the same settings always give the same layout.
+    pub seed: u64,
+}
+
+impl Default for ForceLayoutSettings {
+    fn default() -> Self {
+        Self {
+            // Without gravity, nodes with no edges drift away and squash the rest of the layout
+            force: ForceKind::Handy {
+                gravity: true,
+                centering: true,
+            },
+            iterations: 500,
+            dt: 0.035,
+            scale: 45.0,
+            cooloff_factor: 0.975,
+            node_start_size: 200.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Lays a graph out by running an `fdg-sim` simulation, which unlike [`layered_locations`] also
+/// copes with cycles. Edge direction and multiplicity are ignored.
+pub fn force_directed_locations<N, E>(
+    graph: &Graph<N, E, Directed>,
+    settings: &ForceLayoutSettings,
+) -> NodeLocations {
+    let mut force_graph: ForceGraph<(), (), Undirected> = ForceGraph::default();
+
+    // Node indices are preserved, since nodes are added in the same order
+    for node in graph.node_indices() {
+        force_graph.add_force_node(node.index().to_string(), ());
+    }
+    // Parallel edges are merged, as fdg-sim would otherwise count each of them as a separate
+    // spring. A self-loop has no length to relax, and fdg-sim turns it into a NaN location.
+    let mut edges: Vec<(NodeIndex, NodeIndex)> = graph
+        .edge_references()
+        .map(|edge| (edge.source(), edge.target()))
+        .filter(|(source, target)| source != target)
+        .collect();
+    edges.sort();
+    edges.dedup();
+
+    for (source, target) in edges {
+        force_graph.add_edge(source, target, ());
+    }
+
+    let force: Force<(), (), Undirected> = match settings.force {
+        ForceKind::FruchtermanReingold => {
+            force::fruchterman_reingold(settings.scale, settings.cooloff_factor)
+        }
+        ForceKind::Handy { gravity, centering } => {
+            force::handy(settings.scale, settings.cooloff_factor, gravity, centering)
+        }
+    };
+
+    let mut parameters = SimulationParameters::from_force(force);
+    parameters.node_start_size = settings.node_start_size;
+
+    let mut simulation = Simulation::from_graph(force_graph, parameters);
+
+    // fdg-sim places the nodes with a global generator, which would make every run different
+    let mut rng = StdRng::seed_from_u64(settings.seed);
+    let half_start_size = settings.node_start_size / 2.0;
+    for node in simulation.get_graph_mut().node_weights_mut() {
+        let location = Vec3::new(
+            rng.gen_range(-half_start_size..=half_start_size),
+            rng.gen_range(-half_start_size..=half_start_size),
+            0.0,
+        );
+        node.location = location;
+        node.old_location = location;
+    }
+
+    for _ in 0..settings.iterations {
+        simulation.update(settings.dt);
+    }
+
+    let force_graph = simulation.get_graph();
+
+    graph
+        .node_indices()
+        .map(|node| {
+            let Vec3 { x, y, .. } = force_graph[node].location;
+            (node, (x as f64, y as f64))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn force_layout_handles_cycles() {
+        let mut graph = Graph::<&str, (), Directed>::new();
+        let a = graph.add_node("a");
+        let b = graph.add_node("b");
+        let c = graph.add_node("c");
+        graph.add_edge(a, b, ());
+        graph.add_edge(b, c, ());
+        graph.add_edge(c, a, ());
+        graph.add_edge(b, b, ());
+
+        let locations = force_directed_locations(&graph, &ForceLayoutSettings::default());
+
+        assert_eq!(locations.len(), 3);
+        assert!(locations
+            .values()
+            .all(|(x, y)| x.is_finite() && y.is_finite()));
+        assert_ne!(locations[&a], locations[&b]);
+        assert_ne!(locations[&b], locations[&c]);
+
+        // The initial placement is seeded, so the layout is reproducible
+        assert_eq!(
+            locations,
+            force_directed_locations(&graph, &ForceLayoutSettings::default())
+        );
+        let reseeded = ForceLayoutSettings {
+            seed: 1,
+            ..ForceLayoutSettings::default()
+        };
+        assert_ne!(locations, force_directed_locations(&graph, &reseeded));
+    }
+
+    #[test]
+    fn layered_layout_uses_columns() {
+        let mut graph = Graph::<&str, (), Directed>::new();
+        let a = graph.add_node("a");
+        let b = graph.add_node("b");
+        let c = graph.add_node("c");
+        graph.add_edge(a, b, ());
+        graph.add_edge(a, c, ());
+
+        let locations = layered_locations(&graph);
+
+        assert_eq!(locations[&a], (0.0, 0.0));
+        assert_eq!(locations[&b], (LAYER_SPACING, 0.0));
+        assert_eq!(locations[&c], (LAYER_SPACING, NODE_SPACING));
+
+        // b and c now sit on a cycle, which has no layer, but they still get a place of their own
+        graph.add_edge(b, c, ());
+        graph.add_edge(c, b, ());
+        let locations = layered_locations(&graph);
+        assert_eq!(locations.len(), 3);
+        assert_ne!(locations[&b], locations[&c]);
+        assert_ne!(locations[&b], locations[&a]);
+    }
+}
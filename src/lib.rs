@@ -1,17 +1,36 @@
+pub mod alluvial;
+pub mod annotate;
+pub mod bar_chart;
 pub mod errors;
+pub mod explorer;
+pub mod export;
+pub mod facet;
 pub mod file_op;
+pub mod heatmap;
+pub mod layout;
 pub mod models;
+pub mod node_link;
+pub mod palette;
 pub mod sankey;
 pub mod sankey_graph;
 pub mod settings;
+pub mod spec;
+pub mod swimmer;
+#[cfg(test)]
+mod test_util;
+pub mod theme;
 
 use crate::{
+    alluvial::Alluvial,
     file_op::read_csv_file,
-    models::{crs_dose::AeDoseCsvRecord, DosageEvent},
+    models::{
+        crs_dose::{AeDoseCsvRecord, DoseCsvError},
+        DosageEvent, EdgeDatum,
+    },
     models::{CytokineReleaseSyndromeGrade, Dose},
 };
 use petgraph::{stable_graph::NodeIndex, Directed, Graph};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use strum::IntoEnumIterator;
 
 trait SortAndReturn {
@@ -25,12 +44,18 @@ impl<T: Ord> SortAndReturn for Vec<T> {
     }
 }
 
-pub fn create_crs_graph() -> errors::Result<Graph<DosageEvent, i32, Directed>> {
+pub fn create_crs_graph() -> errors::Result<Graph<DosageEvent, EdgeDatum, Directed>> {
     let records: Vec<AeDoseCsvRecord> = read_csv_file("./dose.csv")?;
 
+    crs_graph_from_records(&records)
+}
+
+pub fn crs_graph_from_records(
+    records: &[AeDoseCsvRecord],
+) -> errors::Result<Graph<DosageEvent, EdgeDatum, Directed>> {
     let mut node_idxs = HashMap::<(Dose, CytokineReleaseSyndromeGrade), NodeIndex>::new();
 
-    let mut graph = Graph::<DosageEvent, i32, Directed>::new();
+    let mut graph = Graph::<DosageEvent, EdgeDatum, Directed>::new();
 
     for dose in Dose::iter() {
         for grade in CytokineReleaseSyndromeGrade::iter() {
@@ -39,15 +64,13 @@ pub fn create_crs_graph() -> errors::Result<Graph<DosageEvent, i32, Directed>> {
         }
     }
 
-    let mut subjects = HashMap::<i32, Vec<AeDoseCsvRecord>>::new();
+    // Ordered by subject, so that edges are added in the same order on every run
+    let mut subjects = BTreeMap::<i32, Vec<AeDoseCsvRecord>>::new();
 
     for record in records.iter() {
         let key = record.subject_id;
 
-        subjects
-            .entry(key)
-            .or_insert_with(Vec::new)
-            .push(record.clone());
+        subjects.entry(key).or_default().push(record.clone());
     }
 
     for (_, dose_events) in subjects.iter() {
@@ -56,7 +79,14 @@ pub fn create_crs_graph() -> errors::Result<Graph<DosageEvent, i32, Directed>> {
         for dose in sorted_dose_events.windows(2) {
             match &dose {
                 &[source, target] => {
-                    assert!(source.dose_number + 1 == target.dose_number);
+                    if source.dose_number + 1 != target.dose_number {
+                        return Err(DoseCsvError::NonConsecutiveDoses {
+                            subject: source.subject_id,
+                            from: source.dose_number,
+                            to: target.dose_number,
+                        }
+                        .into());
+                    }
 
                     let dose: Dose = source.dose_number.try_into()?;
                     let grade: CytokineReleaseSyndromeGrade =
@@ -72,7 +102,14 @@ pub fn create_crs_graph() -> errors::Result<Graph<DosageEvent, i32, Directed>> {
 
                     match (source_idx, target_idx) {
                         (Some(&source_idx), Some(&target_idx)) => {
-                            let _ = graph.add_edge(source_idx, target_idx, 1);
+                            let _ = graph.add_edge(
+                                source_idx,
+                                target_idx,
+                                EdgeDatum {
+                                    weight: 1,
+                                    subject_id: source.subject_id as i64,
+                                },
+                            );
                         }
                         _ => {}
                     }
@@ -84,3 +121,101 @@ pub fn create_crs_graph() -> errors::Result<Graph<DosageEvent, i32, Directed>> {
 
     Ok(graph)
 }
+
+pub fn create_crs_alluvial() -> errors::Result<Alluvial> {
+    let records: Vec<AeDoseCsvRecord> = read_csv_file("./dose.csv")?;
+
+    crs_alluvial_from_records(&records)
+}
+
+pub fn crs_alluvial_from_records(records: &[AeDoseCsvRecord]) -> errors::Result<Alluvial> {
+    let mut alluvial = Alluvial::new();
+
+    let axes = HashMap::<Dose, _>::from_iter(
+        Dose::iter().map(|dose| (dose, alluvial.axis(dose.to_string()))),
+    );
+    let strata = HashMap::<CytokineReleaseSyndromeGrade, _>::from_iter(
+        CytokineReleaseSyndromeGrade::iter()
+            .map(|grade| (grade, alluvial.stratum(grade.to_string(), None))),
+    );
+
+    for record in records.iter() {
+        let dose: Dose = record.dose_number.try_into()?;
+        let grade: CytokineReleaseSyndromeGrade =
+            record.cytokine_release_syndrome_grade_id.try_into()?;
+
+        alluvial.observe(
+            &record.subject_id.to_string(),
+            None,
+            axes[&dose],
+            strata[&grade],
+        );
+    }
+
+    Ok(alluvial)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::crs_dose::{read_dose_records_from, DoseColumns};
+    use crate::sankey_graph::{
+        convert_to_subject_sankey_with_metadata, SankeyLayering, SankeyStyle,
+    };
+    use crate::test_util::node_x;
+
+    static DOSE_SANKEY_FIXTURE: &str = include_str!("../tests/fixtures/dose_sankey.svg");
+
+    fn render_dose_sankey() -> String {
+        let graph = create_crs_graph().unwrap();
+        let sankey = convert_to_subject_sankey_with_metadata(
+            graph,
+            &|event| event.to_string(),
+            &DosageEvent::metadata,
+        );
+        // Layered by dose, so that states no subject reached stay in their dose's column
+        let style = SankeyStyle::<fn(f64) -> String> {
+            layering: Some(SankeyLayering::Metadata("dose".to_string())),
+            ..SankeyStyle::default()
+        };
+        sankey.draw(1000.0, 600.0, style).to_string()
+    }
+
+    #[test]
+    fn dose_sankey_is_deterministic() {
+        let svg = render_dose_sankey();
+
+        assert_eq!(svg, render_dose_sankey());
+        assert_eq!(svg, DOSE_SANKEY_FIXTURE);
+
+        // (D4, G2) has no flow, but is still drawn with the other fourth doses
+        assert_eq!(node_x(&svg, "node-D4-G2"), node_x(&svg, "node-D4-G0"));
+        assert!(node_x(&svg, "node-D4-G2") > node_x(&svg, "node-D1-G0"));
+    }
+
+    #[test]
+    fn gaps_and_repeated_doses_are_errors() {
+        let graph = |csv: &str| {
+            let records = read_dose_records_from(csv.as_bytes(), &DoseColumns::default()).unwrap();
+            crs_graph_from_records(&records)
+        };
+
+        assert!(graph("NSID,AEDOSE,DV\n1,1,0\n1,2,1\n").is_ok());
+
+        for (csv, from, to) in [
+            ("NSID,AEDOSE,DV\n1,1,0\n1,3,1\n", 1, 3),
+            ("NSID,AEDOSE,DV\n1,1,0\n1,1,1\n", 1, 1),
+        ] {
+            let error = graph(csv).unwrap_err();
+            assert!(matches!(
+                error,
+                errors::ChartAppErrors::DoseCsvError(DoseCsvError::NonConsecutiveDoses {
+                    subject: 1,
+                    from: f,
+                    to: t,
+                }) if f == from && t == to
+            ));
+            assert_eq!(error.exit_code(), 65);
+        }
+    }
+}
@@ -1,59 +1,509 @@
-use std::collections::HashMap;
-use svgdom::{Attribute, AttributeId, AttributeValue, Document, ElementId, FilterSvg, PathCommand};
+use std::{
+    collections::BTreeMap,
+    io::Write,
+    path::{Path, PathBuf},
+    process::ExitCode,
+};
+
+use clap::{error::ErrorKind, Args, CommandFactory, Parser, Subcommand, ValueEnum};
+use strum::IntoEnumIterator;
+use svg::node::element::SVG;
 
 use charts::{
-    create_crs_graph, errors,
-    file_op::read_json_file,
-    models::{crs_dose::AeDoseColorHashCsvRecord, DosageEvent},
-    sankey::{LayerOrderingMethod, SankeyLayers},
-    sankey_graph::{convert_to_sankey, SankeyStyle},
+    alluvial::AlluvialStyle,
+    annotate::{annotate_svg, read_color_map, PaintAttribute},
+    crs_alluvial_from_records, crs_graph_from_records, errors,
+    export::{
+        dot::to_dot,
+        html::save_html,
+        image::{save_pdf, save_png, ImageExportOptions},
+        web::{D3SankeyGraph, PlotlySankeyTrace},
+    },
+    heatmap::{draw_small_multiples, transition_matrices, HeatmapStyle},
+    models::{
+        crs_dose::{read_dose_records, AeDoseCsvRecord, DoseColumns},
+        AltGraph, CytokineReleaseSyndromeGrade, DosageEvent, Dose, EdgeDatum,
+    },
+    sankey::LayerOrderingMethod,
+    sankey_graph::{
+        convert_to_subject_sankey_with_metadata, OrdinalScale, SankeyOrientation, SankeyStyle,
+    },
+    spec::{self, render_spec},
+    swimmer::{LaneOrder, SwimmerPlot, SwimmerStyle},
+    theme::Theme,
 };
 
-fn labeller(dosage_event: DosageEvent) -> String {
-    dosage_event.to_string()
-}
-fn main() -> errors::Result<()> {
-    let records: Vec<AeDoseColorHashCsvRecord> = read_json_file("./dose.csv")?;
-
-    let mut color_hash_to_subject_id = HashMap::new();
-    let mut subject_id_to_color_hash = HashMap::new();
-
-    for AeDoseColorHashCsvRecord {
-        subject_id,
-        color_hash,
-        ..
-    } in &records
-    {
-        color_hash_to_subject_id.insert(color_hash, subject_id);
-        subject_id_to_color_hash.insert(subject_id, color_hash);
-    }
-
-    let svg_str = include_str!("../sankey_diagram.svg");
-    let doc = Document::from_str(&svg_str).unwrap();
-
-    let mut count = 0;
-    for (tag, mut node) in doc.root().descendants().svg() {
-        match tag {
-            ElementId::Polygon => {
-                let attrs = node.attributes();
-                if let Some(&AttributeValue::String(ref hash)) =
-                    &attrs.get_value(AttributeId::Color)
-                {
-                    let subject_id = color_hash_to_subject_id.get(hash);
-
-                    if let Some(&&subject_id) = subject_id {
-                        node.set_attribute(Attribute::new(
-                            AttributeId::FontFamily,
-                            AttributeValue::Number(subject_id as f64),
-                        ))
-                    }
-                }
+/// Charts of adverse event grades across doses.
+#[derive(Parser)]
+#[command(name = "charts", version)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Draws a chart of a dose CSV as SVG, PNG, PDF or HTML.
+    Render(RenderArgs),
+    /// Tags the elements of an externally rendered SVG with the subjects drawn in their colour.
+    AnnotateSvg(AnnotateArgs),
+    /// Writes the transition graph as Graphviz DOT, JSON, or Plotly or d3-sankey input.
+    GraphExport(GraphExportArgs),
+    /// Prints a summary of a dose CSV.
+    Stats(StatsArgs),
+    /// Draws the chart described by a JSON or TOML spec file and writes all of its outputs.
+    RenderSpec(RenderSpecArgs),
+}
+
+#[derive(Args)]
+struct InputArgs {
+    /// Dose CSV, with one row per subject and dose.
+    #[arg(short, long, default_value = "./dose.csv")]
+    input: PathBuf,
+    /// Column holding the subject id.
+    #[arg(long, default_value = "NSID")]
+    subject_column: String,
+    /// Column holding the dose number.
+    #[arg(long, default_value = "AEDOSE")]
+    dose_column: String,
+    /// Column holding the grade.
+    #[arg(long, default_value = "DV")]
+    grade_column: String,
+}
+
+impl InputArgs {
+    fn records(&self) -> errors::Result<Vec<AeDoseCsvRecord>> {
+        let columns = DoseColumns {
+            subject: self.subject_column.clone(),
+            dose: self.dose_column.clone(),
+            grade: self.grade_column.clone(),
+            ..DoseColumns::default()
+        };
+
+        Ok(read_dose_records(&self.input, &columns)?)
+    }
+}
+
+#[derive(Args)]
+struct RenderArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[arg(short, long)]
+    output: PathBuf,
+    /// Output format. By default, taken from the extension of the output file.
+    #[arg(short, long)]
+    format: Option<ImageFormat>,
+    #[arg(short, long, default_value = "sankey")]
+    chart: ChartKind,
+    #[arg(long, default_value_t = 1000.0)]
+    width: f64,
+    #[arg(long, default_value_t = 600.0)]
+    height: f64,
+    /// Resolution of PNG and PDF output.
+    #[arg(long)]
+    dpi: Option<f32>,
+    #[command(flatten)]
+    style: StyleArgs,
+}
+
+#[derive(Args)]
+struct StyleArgs {
+    /// Built-in theme.
+    #[arg(long, value_parser = ["light", "dark", "print", "colour-blind"])]
+    theme: Option<String>,
+    /// Theme JSON file, in the format of `assets/themes`.
+    #[arg(long, conflicts_with = "theme")]
+    theme_file: Option<PathBuf>,
+    #[arg(long)]
+    orientation: Option<Orientation>,
+    /// How Sankey nodes are assigned to columns, as `layout.layering` in a spec file.
+    #[arg(long, default_value = "step")]
+    layering: Layering,
+    /// Order of the Sankey nodes within each column. Insertion order if unset.
+    #[arg(long)]
+    ordering: Option<Ordering>,
+    #[arg(long)]
+    font_family: Option<String>,
+    #[arg(long)]
+    font_size: Option<f64>,
+    /// Colour Sankey nodes by grade and add a legend.
+    #[arg(long)]
+    color_grades: bool,
+    /// Also colour ribbons by the grade they lead to.
+    #[arg(long, requires = "color_grades")]
+    color_ribbons: bool,
+    /// Draw faded stubs for subjects entering or leaving between doses.
+    #[arg(long)]
+    show_imbalance: bool,
+}
+
+type SankeyCliStyle = SankeyStyle<fn(f64) -> String>;
+
+impl StyleArgs {
+    fn sankey_style(&self) -> errors::Result<SankeyCliStyle> {
+        let theme = match (&self.theme, &self.theme_file) {
+            (Some(name), _) => Theme::named(name),
+            (None, Some(path)) => Some(Theme::from_json_file(path)?),
+            (None, None) => None,
+        };
+        let ordinal = match self.color_grades {
+            true => Some(OrdinalScale {
+                color_ribbons: self.color_ribbons,
+                ..CytokineReleaseSyndromeGrade::ordinal_scale()
+            }),
+            false => None,
+        };
+
+        Ok(SankeyStyle {
+            font_family: self.font_family.clone(),
+            font_size: self.font_size,
+            show_imbalance: Some(self.show_imbalance),
+            orientation: self.orientation.map(SankeyOrientation::from),
+            layering: Some(spec::Layering::from(self.layering).into()),
+            ordering: self.ordering.map(LayerOrderingMethod::from),
+            theme,
+            ordinal,
+            ..SankeyStyle::default()
+        })
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ChartKind {
+    Sankey,
+    Alluvial,
+    Swimmer,
+    // Transition matrices between consecutive doses.
+    Heatmap,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum ImageFormat {
+    Svg,
+    Png,
+    Pdf,
+    Html,
+}
+
+impl ImageFormat {
+    fn from_path(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        ImageFormat::from_str(&extension, true).ok()
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Orientation {
+    LeftToRight,
+    RightToLeft,
+    TopToBottom,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Layering {
+    LongestPath,
+    // One column per dose.
+    Step,
+}
+
+impl From<Layering> for spec::Layering {
+    fn from(layering: Layering) -> Self {
+        match layering {
+            Layering::LongestPath => spec::Layering::LongestPath,
+            Layering::Step => spec::Layering::Step,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Ordering {
+    Barycenter,
+    Median,
+}
+
+impl From<Ordering> for LayerOrderingMethod {
+    fn from(ordering: Ordering) -> Self {
+        match ordering {
+            Ordering::Barycenter => LayerOrderingMethod::Barycenter,
+            Ordering::Median => LayerOrderingMethod::Median,
+        }
+    }
+}
+
+impl From<Orientation> for SankeyOrientation {
+    fn from(orientation: Orientation) -> Self {
+        match orientation {
+            Orientation::LeftToRight => SankeyOrientation::LeftToRight,
+            Orientation::RightToLeft => SankeyOrientation::RightToLeft,
+            Orientation::TopToBottom => SankeyOrientation::TopToBottom,
+        }
+    }
+}
+
+#[derive(Args)]
+struct AnnotateArgs {
+    /// SVG to annotate.
+    #[arg(short, long)]
+    input: PathBuf,
+    /// JSON records giving each subject's colour, as in `nsid_color_map.json`.
+    #[arg(long)]
+    color_map: PathBuf,
+    /// Paint holding the subject colour.
+    #[arg(long, default_value = "stroke")]
+    paint: Paint,
+    /// Written to standard output if unset.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Paint {
+    Fill,
+    Stroke,
+}
+
+impl From<Paint> for PaintAttribute {
+    fn from(paint: Paint) -> Self {
+        match paint {
+            Paint::Fill => PaintAttribute::Fill,
+            Paint::Stroke => PaintAttribute::Stroke,
+        }
+    }
+}
+
+#[derive(Args)]
+struct GraphExportArgs {
+    #[command(flatten)]
+    input: InputArgs,
+    #[arg(short, long, default_value = "json")]
+    format: GraphFormat,
+    /// Written to standard output if unset.
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    // The `AltGraph` format read back by the explorer.
+    Json,
+    Plotly,
+    D3,
+}
+
+#[derive(Args)]
+struct StatsArgs {
+    #[command(flatten)]
+    input: InputArgs,
+}
+
+#[derive(Args)]
+struct RenderSpecArgs {
+    spec: PathBuf,
+}
+
+fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    let result = match cli.command {
+        Command::Render(args) => render(args),
+        Command::AnnotateSvg(args) => annotate(args),
+        Command::GraphExport(args) => graph_export(args),
+        Command::Stats(args) => stats(args),
+        Command::RenderSpec(args) => render_spec(&args.spec).map(|written| {
+            for path in written {
+                eprintln!("wrote {}", path.display());
             }
-            _ => {}
+        }),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {error}");
+            ExitCode::from(error.exit_code())
         }
     }
+}
+
+fn render(args: RenderArgs) -> errors::Result<()> {
+    let Some(format) = args.format.or_else(|| ImageFormat::from_path(&args.output)) else {
+        Cli::command()
+            .error(
+                ErrorKind::ValueValidation,
+                format!(
+                    "cannot tell the format of {} from its extension, use --format",
+                    args.output.display()
+                ),
+            )
+            .exit()
+    };
+
+    let records = args.input.records()?;
+    let style = args.style.sankey_style()?;
+    let (width, height) = (args.width, args.height);
+
+    let svg: SVG = match args.chart {
+        ChartKind::Sankey => {
+            let graph = crs_graph_from_records(&records)?;
+            convert_to_subject_sankey_with_metadata(
+                graph,
+                &|event| event.to_string(),
+                &DosageEvent::metadata,
+            )
+            .draw(width, height, style)
+        }
+        ChartKind::Alluvial => crs_alluvial_from_records(&records)?.draw(
+            width,
+            height,
+            AlluvialStyle::<fn(f64) -> String> {
+                font_family: style.font_family,
+                font_size: style.font_size,
+                ..AlluvialStyle::default()
+            },
+        ),
+        ChartKind::Swimmer => SwimmerPlot::from_records(&records)?.draw(
+            width,
+            height,
+            SwimmerStyle {
+                lane_order: Some(LaneOrder::MaxGrade),
+                font_family: style.font_family,
+                font_size: style.font_size,
+                ..SwimmerStyle::default()
+            },
+        ),
+        ChartKind::Heatmap => {
+            let graph = crs_graph_from_records(&records)?;
+            let matrices = transition_matrices(
+                &graph,
+                &|event| event.dose,
+                &|event| event.to_string(),
+                &|edge| edge.weight as f64,
+            );
+            draw_small_multiples(
+                &matrices,
+                matrices.len(),
+                width,
+                height,
+                HeatmapStyle::from(style),
+            )
+        }
+    };
+
+    let options = ImageExportOptions {
+        dpi: args.dpi,
+        ..ImageExportOptions::default()
+    };
+
+    match format {
+        ImageFormat::Svg => svg::save(&args.output, &svg)?,
+        ImageFormat::Png => save_png(&args.output, &svg, &options)?,
+        ImageFormat::Pdf => save_pdf(&args.output, &svg, &options)?,
+        ImageFormat::Html => save_html(&args.output, &svg, "Adverse events by dose")?,
+    }
+
+    Ok(())
+}
+
+fn annotate(args: AnnotateArgs) -> errors::Result<()> {
+    let color_map = read_color_map(&args.color_map)?;
+    let svg = std::fs::read_to_string(&args.input)?;
 
-    // svg::save("./example.svg", &svg).unwrap();
+    let (annotated, count) = annotate_svg(&svg, &color_map, args.paint.into())?;
+    eprintln!("annotated {count} elements");
+
+    write_output(args.output.as_deref(), &annotated)
+}
+
+fn graph_export(args: GraphExportArgs) -> errors::Result<()> {
+    let graph = crs_graph_from_records(&args.input.records()?)?;
+    let labeller = |event: DosageEvent| event.to_string();
+    let weight = |edge: &EdgeDatum| edge.weight as f64;
+
+    let contents = match args.format {
+        GraphFormat::Dot => to_dot(&graph, &labeller, &|_| None, &weight),
+        GraphFormat::Json => serde_json::to_string_pretty(&AltGraph::from_graph(&graph))?,
+        GraphFormat::Plotly => serde_json::to_string_pretty(&PlotlySankeyTrace::from(
+            &convert_to_subject_sankey_with_metadata(graph, &labeller, &DosageEvent::metadata),
+        ))?,
+        GraphFormat::D3 => serde_json::to_string_pretty(&D3SankeyGraph::from(
+            &convert_to_subject_sankey_with_metadata(graph, &labeller, &DosageEvent::metadata),
+        ))?,
+    };
+
+    write_output(args.output.as_deref(), &contents)
+}
+
+fn stats(args: StatsArgs) -> errors::Result<()> {
+    let records = args.input.records()?;
+
+    let mut subjects = BTreeMap::<i32, usize>::new();
+    let mut grades = BTreeMap::<(Dose, CytokineReleaseSyndromeGrade), usize>::new();
+
+    for record in &records {
+        let dose: Dose = record.dose_number.try_into()?;
+        let grade: CytokineReleaseSyndromeGrade =
+            record.cytokine_release_syndrome_grade_id.try_into()?;
+
+        *subjects.entry(record.subject_id).or_default() += 1;
+        *grades.entry((dose, grade)).or_default() += 1;
+    }
+
+    let graph = crs_graph_from_records(&records)?;
+    let sankey = convert_to_subject_sankey_with_metadata(
+        graph.clone(),
+        &|event| event.to_string(),
+        &DosageEvent::metadata,
+    );
+
+    println!("Records:     {}", records.len());
+    println!("Subjects:    {}", subjects.len());
+    println!("Transitions: {}", graph.edge_count());
+    println!();
+
+    print!("{:<6}", "Dose");
+    for grade in CytokineReleaseSyndromeGrade::iter() {
+        print!("{:>6}", grade.to_string());
+    }
+    println!();
+    for dose in Dose::iter() {
+        print!("{:<6}", dose.to_string());
+        for grade in CytokineReleaseSyndromeGrade::iter() {
+            print!("{:>6}", grades.get(&(dose, grade)).unwrap_or(&0));
+        }
+        println!();
+    }
+
+    // Subjects entering after the first dose or leaving before the last, as drawn by the
+    // imbalance stubs
+    let unaccounted: Vec<_> = sankey
+        .node_ids()
+        .map(|node| {
+            (
+                node,
+                sankey.unaccounted_input(node),
+                sankey.unaccounted_output(node),
+            )
+        })
+        .filter(|&(_, input, output)| input > 0.0 || output > 0.0)
+        .collect();
+    if !unaccounted.is_empty() {
+        println!();
+        println!("{:<12}{:>8}{:>8}", "Unaccounted", "In", "Out");
+        for (node, input, output) in unaccounted {
+            let label = sankey.node_label(node).unwrap_or_default();
+            println!("{label:<12}{input:>8}{output:>8}");
+        }
+    }
+
+    Ok(())
+}
+
+fn write_output(path: Option<&Path>, contents: &str) -> errors::Result<()> {
+    match path {
+        Some(path) => std::fs::write(path, contents)?,
+        None => std::io::stdout().write_all(contents.as_bytes())?,
+    }
 
     Ok(())
 }
@@ -1,5 +1,8 @@
 use serde::{self, Deserialize, Serialize};
 use std::cmp::Ordering;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 
 #[derive(Debug, Deserialize, Serialize, Clone, Eq, PartialEq, PartialOrd)]
 pub struct AeDoseCsvRecord {
@@ -37,3 +40,139 @@ impl Ord for AeDoseCsvRecord {
         self.dose_number.cmp(&other.dose_number)
     }
 }
+
+#[derive(thiserror::Error, Debug)]
+pub enum DoseCsvError {
+    #[error(transparent)]
+    CsvError(#[from] csv::Error),
+    #[error("The CSV has no `{0}` column")]
+    MissingColumn(String),
+    #[error("Row {row}: `{value}` in column `{column}` is not a whole number")]
+    InvalidNumber {
+        row: usize,
+        column: String,
+        value: String,
+    },
+    #[error("Subject {subject} goes from dose {from} to dose {to}, but each subject's doses must be consecutive and listed once")]
+    NonConsecutiveDoses { subject: i32, from: i32, to: i32 },
+}
+
+/// Names of the CSV columns holding each field of an [`AeDoseCsvRecord`], for files that don't
+/// use the standard `NSID`, `AEDOSE` and `DV` headers.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DoseColumns {
+    pub subject: String,
+    pub dose: String,
+    pub grade: String,
+    /// Optional columns, left empty if missing.
+    pub date: String,
+    pub time: String,
+}
+
+impl Default for DoseColumns {
+    fn default() -> Self {
+        DoseColumns {
+            subject: "NSID".to_string(),
+            dose: "AEDOSE".to_string(),
+            grade: "DV".to_string(),
+            date: "DATE".to_string(),
+            time: "TIME".to_string(),
+        }
+    }
+}
+
+/// Reads dose records from a CSV file, taking each field from the column named in `columns`.
+pub fn read_dose_records<P: AsRef<Path>>(
+    path: P,
+    columns: &DoseColumns,
+) -> Result<Vec<AeDoseCsvRecord>, DoseCsvError> {
+    read_dose_records_from(File::open(path).map_err(csv::Error::from)?, columns)
+}
+
+/// Like [`read_dose_records`], but reads the CSV from any reader, such as an in-memory string.
+pub fn read_dose_records_from<R: Read>(
+    reader: R,
+    columns: &DoseColumns,
+) -> Result<Vec<AeDoseCsvRecord>, DoseCsvError> {
+    let mut reader = csv::ReaderBuilder::new()
+        .flexible(false)
+        .from_reader(reader);
+
+    let headers = reader.headers()?.clone();
+    let find = |name: &str| headers.iter().position(|header| header == name);
+    let require =
+        |name: &str| find(name).ok_or_else(|| DoseCsvError::MissingColumn(name.to_string()));
+
+    let subject = require(&columns.subject)?;
+    let dose = require(&columns.dose)?;
+    let grade = require(&columns.grade)?;
+    let date = find(&columns.date);
+    let time = find(&columns.time);
+
+    let mut records = Vec::new();
+
+    for (index, row) in reader.records().enumerate() {
+        let row = row?;
+        // Counting the header as row 1, as spreadsheets do
+        let number = |field: usize, column: &str| -> Result<i32, DoseCsvError> {
+            let value = &row[field];
+            value
+                .trim()
+                .parse()
+                .map_err(|_| DoseCsvError::InvalidNumber {
+                    row: index + 2,
+                    column: column.to_string(),
+                    value: value.to_string(),
+                })
+        };
+
+        records.push(AeDoseCsvRecord {
+            subject_id: number(subject, &columns.subject)?,
+            dose_number: number(dose, &columns.dose)?,
+            cytokine_release_syndrome_grade_id: number(grade, &columns.grade)?,
+            date: date.map_or(String::new(), |field| row[field].to_string()),
+            // "." marks a missing time, which `csv::invalid_option` reads as None
+            time: time
+                .map(|field| row[field].to_string())
+                .filter(|time| !time.is_empty() && time != "."),
+        });
+    }
+
+    Ok(records)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_renamed_columns() {
+        let columns = DoseColumns {
+            subject: "ID".to_string(),
+            dose: "Dose".to_string(),
+            grade: "Grade".to_string(),
+            ..DoseColumns::default()
+        };
+        let error = read_dose_records_from("ID,Dose,Grade\n7,1,0\n7,2,x\n".as_bytes(), &columns)
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Row 3: `x` in column `Grade` is not a whole number"
+        );
+
+        let csv = "ID,Dose,Grade\n7,1,0\n7,2,1\n";
+        let records = read_dose_records_from(csv.as_bytes(), &columns).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[1].cytokine_release_syndrome_grade_id, 1);
+        assert_eq!(records[1].time, None);
+
+        assert!(matches!(
+            read_dose_records_from(csv.as_bytes(), &DoseColumns::default()),
+            Err(DoseCsvError::MissingColumn(column)) if column == "NSID"
+        ));
+        assert!(matches!(
+            read_dose_records("missing.csv", &columns),
+            Err(DoseCsvError::CsvError(error)) if error.is_io_error()
+        ));
+    }
+}
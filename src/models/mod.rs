@@ -1,9 +1,18 @@
 pub mod crs_dose;
 
-use std::{fmt, fs::File, io::Write, path::Path};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    fs::File,
+    io::Write,
+    path::Path,
+};
 
+use petgraph::{graph::NodeIndex, Directed, Graph};
 use serde::{Deserialize, Serialize};
 
+use crate::sankey_graph::{OrdinalScale, SubjectFlow};
+
 #[derive(
     Hash,
     Serialize,
@@ -29,6 +38,19 @@ pub enum EnumIntConversionError {
     #[error("The integer`{0}` could not be converted into the enum")]
     FromIntError(i32),
 }
+impl CytokineReleaseSyndromeGrade {
+    /// Colour scale for the grades, which [`DosageEvent::metadata`] stores as `grade`.
+    pub fn ordinal_scale() -> OrdinalScale {
+        use strum::IntoEnumIterator;
+        OrdinalScale::new(
+            "grade",
+            CytokineReleaseSyndromeGrade::iter()
+                .map(|grade| grade.to_string())
+                .collect(),
+        )
+    }
+}
+
 impl TryFrom<i32> for CytokineReleaseSyndromeGrade {
     type Error = EnumIntConversionError;
 
@@ -79,26 +101,36 @@ impl TryFrom<i32> for Dose {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct NodeDatum {
     pub grade: CytokineReleaseSyndromeGrade,
     pub dose: Dose,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Node {
     pub id: String,
     // label: String,
     pub datum: NodeDatum,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
 pub struct EdgeDatum {
     pub weight: i64,
     pub subject_id: i64,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl SubjectFlow for EdgeDatum {
+    fn value(&self) -> f64 {
+        self.weight as f64
+    }
+
+    fn subject(&self) -> String {
+        self.subject_id.to_string()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Edge {
     pub id: String,
     pub source: String,
@@ -112,13 +144,110 @@ pub struct AltGraph {
     pub edges: Vec<Edge>,
 }
 
+#[derive(thiserror::Error, Debug)]
+pub enum AltGraphError {
+    #[error("The node id `{0}` is used more than once")]
+    DuplicateNodeId(String),
+    #[error("The edge id `{0}` is used more than once")]
+    DuplicateEdgeId(String),
+    #[error("The edge `{0}` refers to the unknown node `{1}`")]
+    UnknownEndpoint(String, String),
+}
+
+impl AltGraph {
+    /// Builds the transition graph described by this file, checking that node and edge ids are
+    /// unique and that every edge connects two declared nodes.
+    pub fn to_graph(&self) -> Result<Graph<DosageEvent, EdgeDatum, Directed>, AltGraphError> {
+        let mut graph = Graph::<DosageEvent, EdgeDatum, Directed>::new();
+
+        let mut node_idxs = HashMap::<&str, NodeIndex>::new();
+
+        for node in &self.nodes {
+            let NodeDatum { grade, dose } = node.datum;
+            let node_idx = graph.add_node(DosageEvent { grade, dose });
+            if node_idxs.insert(&node.id, node_idx).is_some() {
+                return Err(AltGraphError::DuplicateNodeId(node.id.clone()));
+            }
+        }
+
+        let mut edge_ids = HashSet::<&str>::new();
+
+        for edge in &self.edges {
+            if !edge_ids.insert(&edge.id) {
+                return Err(AltGraphError::DuplicateEdgeId(edge.id.clone()));
+            }
+
+            let endpoint = |id: &String| {
+                node_idxs
+                    .get(id.as_str())
+                    .copied()
+                    .ok_or_else(|| AltGraphError::UnknownEndpoint(edge.id.clone(), id.clone()))
+            };
+
+            graph.add_edge(endpoint(&edge.source)?, endpoint(&edge.target)?, edge.datum);
+        }
+
+        Ok(graph)
+    }
+
+    /// The inverse of [`AltGraph::to_graph`]. Nodes are identified as `<dose>.<grade>` and edges as
+    /// `<subject>-<source>-<target>`, matching `data.json`.
+    pub fn from_graph(graph: &Graph<DosageEvent, EdgeDatum, Directed>) -> Self {
+        let node_id = |idx: NodeIndex| {
+            let DosageEvent { grade, dose } = graph[idx];
+            format!("{dose}.{grade}")
+        };
+
+        let nodes = graph
+            .node_indices()
+            .map(|idx| {
+                let DosageEvent { grade, dose } = graph[idx];
+                Node {
+                    id: node_id(idx),
+                    datum: NodeDatum { grade, dose },
+                }
+            })
+            .collect();
+
+        let mut edge_ids = HashSet::<String>::new();
+
+        let edges = graph
+            .edge_indices()
+            .map(|idx| {
+                let (source, target) = graph.edge_endpoints(idx).unwrap();
+                let datum = graph[idx];
+
+                let mut id = format!(
+                    "{}-{}-{}",
+                    datum.subject_id,
+                    node_id(source),
+                    node_id(target)
+                );
+                // A subject repeating the same transition still needs a unique edge id
+                if edge_ids.contains(&id) {
+                    id = format!("{id}-{}", idx.index());
+                }
+                edge_ids.insert(id.clone());
+
+                Edge {
+                    id,
+                    source: node_id(source),
+                    target: node_id(target),
+                    datum,
+                }
+            })
+            .collect();
+
+        AltGraph { nodes, edges }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum DeserializeFromFileError {
     #[error(transparent)]
     IoError(#[from] std::io::Error),
-    // #[error(transparent)]
-
-    // DeseraialiseError(#[from])
+    #[error(transparent)]
+    DeserialiseError(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -130,7 +259,17 @@ pub struct DosageEvent {
 
 impl fmt::Display for DosageEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "({}, {})", self.grade, self.dose)
+        write!(f, "({}, {})", self.dose, self.grade)
+    }
+}
+
+impl DosageEvent {
+    /// The dose and grade, for [`crate::sankey_graph::convert_to_sankey_with_metadata`].
+    pub fn metadata(&self) -> Vec<(String, String)> {
+        vec![
+            ("dose".to_string(), self.dose.to_string()),
+            ("grade".to_string(), self.grade.to_string()),
+        ]
     }
 }
 
@@ -139,7 +278,7 @@ pub fn deserialise_from_file<Json: Serialize + for<'a> Deserialize<'a>>(
 ) -> Result<Json, DeserializeFromFileError> {
     let rdr = File::open(path)?;
 
-    let json: Json = serde_json::from_reader(rdr).expect("JSON was not well-formatted");
+    let json: Json = serde_json::from_reader(rdr)?;
 
     Ok(json)
 }
@@ -149,3 +288,59 @@ pub fn save_to_file(path: impl AsRef<Path>, content: &str) -> std::io::Result<()
     file.write_all(content.as_bytes())?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alt_graph() -> AltGraph {
+        serde_json::from_str(include_str!("../../data.json")).unwrap()
+    }
+
+    #[test]
+    fn alt_graph_round_trip() {
+        let alt_graph = alt_graph();
+        let graph = alt_graph.to_graph().unwrap();
+
+        assert_eq!(graph.node_count(), alt_graph.nodes.len());
+        assert_eq!(graph.edge_count(), alt_graph.edges.len());
+
+        let exported = AltGraph::from_graph(&graph);
+        assert_eq!(
+            serde_json::to_value(&exported).unwrap(),
+            serde_json::to_value(&alt_graph).unwrap()
+        );
+    }
+
+    #[test]
+    fn alt_graph_validation() {
+        let mut duplicate_node = alt_graph();
+        duplicate_node.nodes.push(duplicate_node.nodes[0].clone());
+        assert!(matches!(
+            duplicate_node.to_graph(),
+            Err(AltGraphError::DuplicateNodeId(id)) if id == "D1.G0"
+        ));
+
+        let mut duplicate_edge = alt_graph();
+        duplicate_edge.edges.push(duplicate_edge.edges[0].clone());
+        assert!(matches!(
+            duplicate_edge.to_graph(),
+            Err(AltGraphError::DuplicateEdgeId(_))
+        ));
+
+        let mut dangling_edge = alt_graph();
+        dangling_edge.edges[0].target = "D9.G9".to_string();
+        assert!(matches!(
+            dangling_edge.to_graph(),
+            Err(AltGraphError::UnknownEndpoint(_, node)) if node == "D9.G9"
+        ));
+    }
+
+    #[test]
+    fn malformed_json_is_an_error() {
+        assert!(matches!(
+            deserialise_from_file::<AltGraph>("dose.csv"),
+            Err(DeserializeFromFileError::DeserialiseError(_))
+        ));
+    }
+}
@@ -0,0 +1,506 @@
+use std::collections::BTreeMap;
+use std::fmt::Display;
+
+use petgraph::graph::{Graph, NodeIndex};
+use petgraph::visit::EdgeRef;
+use petgraph::Directed;
+use svg::{
+    node::{
+        self,
+        element::{path, Circle, Group, Path, Rectangle, Style, Text, SVG},
+    },
+    Node,
+};
+
+use crate::layout::NodeLocations;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NodeShape {
+    Circle,
+    Rectangle,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeShape {
+    Straight,
+    // Bends every edge to its left, so that a pair of opposite edges do not overlap.
+    Curved,
+}
+
+pub struct NodeLinkStyle<F: Fn(f64) -> String> {
+    pub number_format: Option<F>,
+    pub node_shape: Option<NodeShape>,
+    pub node_radius: Option<f64>,
+    pub edge_shape: Option<EdgeShape>,
+    pub min_edge_width: Option<f64>,
+    pub max_edge_width: Option<f64>,
+    pub font_family: Option<String>,
+    pub font_size: Option<f64>,
+    pub font_color: Option<String>,
+    pub border: Option<f64>,
+}
+
+impl<F: Fn(f64) -> String> Default for NodeLinkStyle<F> {
+    fn default() -> Self {
+        NodeLinkStyle {
+            number_format: None,
+            node_shape: None,
+            node_radius: None,
+            edge_shape: None,
+            min_edge_width: None,
+            max_edge_width: None,
+            font_family: None,
+            font_size: None,
+            font_color: None,
+            border: None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct NodeLinkNodeID(usize);
+
+pub struct NodeLinkNode {
+    location: (f64, f64),
+    label: Option<String>,
+    color: Option<String>,
+}
+
+pub struct NodeLinkEdge {
+    source: NodeLinkNodeID,
+    target: NodeLinkNodeID,
+    value: f64,
+    label: Option<String>,
+    color: Option<String>,
+}
+
+/// A node-link (state) diagram: nodes at fixed locations joined by arrows whose width is
+/// proportional to their value.
+#[derive(Default)]
+pub struct NodeLink {
+    nodes: Vec<NodeLinkNode>,
+    edges: Vec<NodeLinkEdge>,
+}
+
+type Point = (f64, f64);
+
+fn sub(a: Point, b: Point) -> Point {
+    (a.0 - b.0, a.1 - b.1)
+}
+
+fn add_scaled(a: Point, d: Point, t: f64) -> Point {
+    (a.0 + d.0 * t, a.1 + d.1 * t)
+}
+
+fn normalise(d: Point) -> Point {
+    let length = d.0.hypot(d.1);
+    match length > 0.0 {
+        true => (d.0 / length, d.1 / length),
+        false => (1.0, 0.0),
+    }
+}
+
+impl NodeLink {
+    pub fn new() -> NodeLink {
+        NodeLink::default()
+    }
+
+    pub fn node(
+        &mut self,
+        location: (f64, f64),
+        label: Option<String>,
+        color: Option<String>,
+    ) -> NodeLinkNodeID {
+        let id = self.nodes.len();
+        self.nodes.push(NodeLinkNode {
+            location,
+            label,
+            color,
+        });
+        NodeLinkNodeID(id)
+    }
+
+    pub fn edge(
+        &mut self,
+        source: NodeLinkNodeID,
+        target: NodeLinkNodeID,
+        value: f64,
+        label: Option<String>,
+        color: Option<String>,
+    ) {
+        self.edges.push(NodeLinkEdge {
+            source,
+            target,
+            value,
+            label,
+            color,
+        });
+    }
+
+    pub fn draw<F: Fn(f64) -> String>(
+        &self,
+        width: f64,
+        height: f64,
+        style: NodeLinkStyle<F>,
+    ) -> SVG {
+        let node_shape = style.node_shape.unwrap_or(NodeShape::Circle);
+        let node_radius = style.node_radius.unwrap_or(height / 40.0);
+        let edge_shape = style.edge_shape.unwrap_or(EdgeShape::Curved);
+        let min_edge_width = style.min_edge_width.unwrap_or(1.0);
+        let max_edge_width = style.max_edge_width.unwrap_or(node_radius);
+        let font_family: String = style.font_family.unwrap_or("sans-serif".to_string());
+        let font_size: f64 = style.font_size.unwrap_or(height / 50.0);
+        let font_color: String = style.font_color.unwrap_or("#000".to_string());
+        let border: f64 = style.border.unwrap_or(height / 10.0);
+
+        // Initialise SVG
+
+        let mut document = SVG::new();
+
+        document.assign("viewBox", (0.0, 0.0, width, height));
+
+        document.append(Style::new(format!(
+            ".node > circle, .node > rect {{
+	fill: #000F;
+}}
+
+.edge > path {{
+	fill: none;
+	stroke: #0006;
+}}
+
+.edge > path.arrow {{
+	fill: #0006;
+	stroke: none;
+}}
+
+.node > text, .edge > text {{
+	fill: {font_color};
+	text-anchor: middle;
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}
+
+.edge:not(:hover) > text {{
+	display: none;
+}}"
+        )));
+
+        // Fit the layout into the drawing area, keeping its aspect ratio
+
+        let (min_x, max_x, min_y, max_y) = self.nodes.iter().fold(
+            (
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+            ),
+            |(min_x, max_x, min_y, max_y), node| {
+                let (x, y) = node.location;
+                (min_x.min(x), max_x.max(x), min_y.min(y), max_y.max(y))
+            },
+        );
+
+        let inner_width = width - border * 2.0;
+        let inner_height = height - border * 2.0;
+        let scale = f64::min(
+            match max_x > min_x {
+                true => inner_width / (max_x - min_x),
+                false => f64::INFINITY,
+            },
+            match max_y > min_y {
+                true => inner_height / (max_y - min_y),
+                false => f64::INFINITY,
+            },
+        );
+        let scale = match scale.is_finite() {
+            true => scale,
+            false => 1.0,
+        };
+        let offset_x = border + (inner_width - (max_x - min_x) * scale) / 2.0;
+        let offset_y = border + (inner_height - (max_y - min_y) * scale) / 2.0;
+
+        let positions: Vec<Point> = self
+            .nodes
+            .iter()
+            .map(|node| {
+                let (x, y) = node.location;
+                (
+                    offset_x + (x - min_x) * scale,
+                    offset_y + (y - min_y) * scale,
+                )
+            })
+            .collect();
+
+        // Distance from a node's centre to its outline, in direction `d`
+        let boundary = |d: Point| match node_shape {
+            NodeShape::Circle => node_radius,
+            NodeShape::Rectangle => {
+                let (dx, dy) = (d.0.abs(), d.1.abs());
+                f64::min(
+                    match dx > 0.0 {
+                        true => node_radius / dx,
+                        false => f64::INFINITY,
+                    },
+                    match dy > 0.0 {
+                        true => node_radius / dy,
+                        false => f64::INFINITY,
+                    },
+                )
+            }
+        };
+
+        let number = |value: f64| {
+            style
+                .number_format
+                .as_ref()
+                .map_or(value.to_string(), |f| f(value))
+        };
+
+        // Generate edges
+
+        let max_value = self.edges.iter().map(|edge| edge.value).fold(0.0, f64::max);
+
+        let mut svg_edges = Vec::new();
+
+        for edge in &self.edges {
+            let thickness = match max_value > 0.0 {
+                true => min_edge_width + (max_edge_width - min_edge_width) * edge.value / max_value,
+                false => min_edge_width,
+            };
+            let arrow_length = thickness * 2.0 + 4.0;
+            let arrow_half_width = thickness + 2.0;
+
+            let source = positions[edge.source.0];
+            let target = positions[edge.target.0];
+
+            let mut data = path::Data::new();
+            let (tip, direction, label_position);
+
+            if edge.source.0 == edge.target.0 {
+                // Self-loops leave the top left of the node and come back in at the top right
+                let loop_height = node_radius * 4.0;
+                let start = (source.0 - node_radius / 2.0, source.1 - node_radius);
+                tip = (source.0 + node_radius / 2.0, source.1 - node_radius);
+                let control_1 = (source.0 - node_radius * 2.0, source.1 - loop_height);
+                let control_2 = (source.0 + node_radius * 2.0, source.1 - loop_height);
+                direction = normalise(sub(tip, control_2));
+                let end = add_scaled(tip, direction, -arrow_length);
+
+                data = data.move_to(start).cubic_curve_to((
+                    control_1.0,
+                    control_1.1,
+                    control_2.0,
+                    control_2.1,
+                    end.0,
+                    end.1,
+                ));
+                label_position = (source.0, source.1 - loop_height * 0.75);
+            } else {
+                let control = match edge_shape {
+                    EdgeShape::Straight => None,
+                    EdgeShape::Curved => {
+                        let d = sub(target, source);
+                        let middle = add_scaled(source, d, 0.5);
+                        Some(add_scaled(middle, (d.1, -d.0), 0.15))
+                    }
+                };
+
+                let towards_target = normalise(sub(target, control.unwrap_or(source)));
+                let towards_source = normalise(sub(source, control.unwrap_or(target)));
+                let start = add_scaled(source, towards_source, -boundary(towards_source));
+                tip = add_scaled(target, towards_target, -boundary(towards_target));
+                direction = towards_target;
+                let end = add_scaled(tip, direction, -arrow_length);
+
+                data = data.move_to(start);
+                data = match control {
+                    Some(control) => data.quadratic_curve_to((control.0, control.1, end.0, end.1)),
+                    None => data.line_to(end),
+                };
+                label_position = match control {
+                    // The midpoint of a quadratic bezier lies halfway between its chord and control point
+                    Some(control) => {
+                        let chord_middle = add_scaled(source, sub(target, source), 0.5);
+                        add_scaled(chord_middle, sub(control, chord_middle), 0.5)
+                    }
+                    None => add_scaled(source, sub(target, source), 0.5),
+                };
+            }
+
+            let mut group = Group::new();
+            group.assign("class", "edge");
+
+            let mut line = Path::new();
+            line.assign("d", data);
+            line.assign("stroke-width", thickness);
+            if let Some(color) = edge.color.as_deref() {
+                line.assign("style", format!("stroke:{color}"));
+            }
+            group.append(line);
+
+            let base = add_scaled(tip, direction, -arrow_length);
+            let normal = (-direction.1, direction.0);
+            let mut arrow = Path::new();
+            arrow.assign("class", "arrow");
+            arrow.assign(
+                "d",
+                path::Data::new()
+                    .move_to(tip)
+                    .line_to(add_scaled(base, normal, arrow_half_width))
+                    .line_to(add_scaled(base, normal, -arrow_half_width))
+                    .close(),
+            );
+            if let Some(color) = edge.color.as_deref() {
+                arrow.assign("style", format!("fill:{color}"));
+            }
+            group.append(arrow);
+
+            let mut text = Text::new();
+            text.assign("x", label_position.0);
+            text.assign("y", label_position.1);
+            let label = match &edge.label {
+                Some(label) => format!("{label}: {}", number(edge.value)),
+                None => number(edge.value),
+            };
+            text.append(node::Text::new(label));
+            group.append(text);
+
+            svg_edges.push((edge.value, group));
+        }
+
+        // Generate nodes
+
+        let mut svg_nodes = Vec::new();
+
+        for (node, &(x, y)) in self.nodes.iter().zip(&positions) {
+            let mut group = Group::new();
+            group.assign("class", "node");
+
+            let fill = node.color.as_deref().map(|color| format!("fill:{color}"));
+            match node_shape {
+                NodeShape::Circle => {
+                    let mut circle = Circle::new();
+                    circle.assign("cx", x);
+                    circle.assign("cy", y);
+                    circle.assign("r", node_radius);
+                    if let Some(fill) = fill {
+                        circle.assign("style", fill);
+                    }
+                    group.append(circle);
+                }
+                NodeShape::Rectangle => {
+                    let mut rect = Rectangle::new();
+                    rect.assign("x", x - node_radius);
+                    rect.assign("y", y - node_radius);
+                    rect.assign("width", node_radius * 2.0);
+                    rect.assign("height", node_radius * 2.0);
+                    if let Some(fill) = fill {
+                        rect.assign("style", fill);
+                    }
+                    group.append(rect);
+                }
+            }
+
+            if let Some(label) = &node.label {
+                let mut text = Text::new();
+                text.assign("x", x);
+                text.assign("y", y + node_radius + font_size);
+                text.append(node::Text::new(label));
+                group.append(text);
+            }
+
+            svg_nodes.push(group);
+        }
+
+        // Add to SVG
+
+        svg_edges.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
+
+        for (_, edge) in svg_edges {
+            document.append(edge);
+        }
+
+        for node in svg_nodes {
+            document.append(node);
+        }
+
+        document
+    }
+}
+
+/// Builds a node-link diagram from a graph and a layout, such as
+/// [`crate::layout::layered_locations`] or [`crate::layout::force_directed_locations`].
+/// Parallel edges are merged into a single arrow whose value is the sum of their weights.
+pub fn convert_to_node_link<N: Clone + Display, E>(
+    graph: &Graph<N, E, Directed>,
+    locations: &NodeLocations,
+    node_labeller: &dyn Fn(N) -> String,
+    edge_weight: &dyn Fn(&E) -> f64,
+) -> NodeLink {
+    let mut node_link = NodeLink::new();
+
+    let mut node_mapping = BTreeMap::<NodeIndex, NodeLinkNodeID>::new();
+
+    for node in graph.node_indices() {
+        let label = node_labeller(graph[node].clone());
+        let location = locations.get(&node).copied().unwrap_or_default();
+        node_mapping.insert(node, node_link.node(location, Some(label), None));
+    }
+
+    let mut values = BTreeMap::<(NodeIndex, NodeIndex), f64>::new();
+    for edge in graph.edge_references() {
+        *values.entry((edge.source(), edge.target())).or_default() += edge_weight(edge.weight());
+    }
+
+    for ((source, target), value) in values {
+        node_link.edge(
+            node_mapping[&source],
+            node_mapping[&target],
+            value,
+            None,
+            None,
+        );
+    }
+
+    node_link
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn draws_merged_edges_and_loops() {
+        let mut graph = Graph::<&str, f64, Directed>::new();
+        let a = graph.add_node("a");
+        let b = graph.add_node("b");
+        graph.add_edge(a, b, 1.0);
+        graph.add_edge(a, b, 1.0);
+        graph.add_edge(b, a, 1.0);
+        graph.add_edge(b, b, 1.0);
+
+        let locations = NodeLocations::from([(a, (0.0, 0.0)), (b, (1.0, 0.0))]);
+        let node_link = convert_to_node_link(&graph, &locations, &|n| n.to_string(), &|&w| w);
+
+        let svg = node_link
+            .draw(
+                400.0,
+                300.0,
+                NodeLinkStyle::<fn(f64) -> String> {
+                    node_shape: Some(NodeShape::Rectangle),
+                    border: Some(50.0),
+                    ..NodeLinkStyle::default()
+                },
+            )
+            .to_string();
+
+        assert_eq!(svg.matches("class=\"edge\"").count(), 3);
+        assert_eq!(svg.matches("class=\"arrow\"").count(), 3);
+        assert_eq!(svg.matches("<rect").count(), 2);
+        // Nodes are stretched across the width, and centred vertically
+        assert!(svg.contains("x=\"42.5\" y=\"142.5\""));
+        assert!(svg.contains("x=\"342.5\" y=\"142.5\""));
+    }
+}
@@ -0,0 +1,420 @@
+use std::fmt::Display;
+
+#[derive(thiserror::Error, Debug)]
+pub enum PaletteError {
+    #[error("`{0}` is not a colour of the form #RGB or #RRGGBB")]
+    InvalidColor(String),
+    #[error("There is no palette called `{0}`")]
+    UnknownPalette(String),
+}
+
+/// An sRGB colour, written as `#RRGGBB`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Rgb {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+}
+
+impl Rgb {
+    pub fn new(r: u8, g: u8, b: u8) -> Rgb {
+        Rgb { r, g, b }
+    }
+
+    /// Parses `#RGB` or `#RRGGBB`. A trailing alpha channel (`#RGBA`, `#RRGGBBAA`) is ignored.
+    pub fn from_hex(hex: &str) -> Result<Rgb, PaletteError> {
+        let invalid = || PaletteError::InvalidColor(hex.to_string());
+        let digits = hex.strip_prefix('#').ok_or_else(invalid)?;
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let channel = |text: &str| u8::from_str_radix(text, 16).map_err(|_| invalid());
+
+        match digits.len() {
+            3 | 4 => {
+                let short = |i: usize| channel(&digits[i..i + 1]).map(|v| v * 17);
+                Ok(Rgb::new(short(0)?, short(1)?, short(2)?))
+            }
+            6 | 8 => Ok(Rgb::new(
+                channel(&digits[0..2])?,
+                channel(&digits[2..4])?,
+                channel(&digits[4..6])?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Coordinates in Oklab, a perceptually uniform colour space: equal distances look like
+    /// roughly equal differences in colour.
+    pub fn to_oklab(self) -> [f64; 3] {
+        linear_to_oklab(self.to_linear())
+    }
+
+    /// Nearest sRGB colour to the given Oklab coordinates.
+    pub fn from_oklab(lab: [f64; 3]) -> Rgb {
+        Rgb::from_linear(oklab_to_linear(lab))
+    }
+
+    /// Perceptual distance to another colour, as the Euclidean distance in Oklab. Around 0.02 is
+    /// just noticeable side by side.
+    pub fn distance(self, other: Rgb) -> f64 {
+        let (a, b) = (self.to_oklab(), other.to_oklab());
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+    }
+
+    /// How the colour looks to someone with the given colour-vision deficiency.
+    pub fn simulate(self, deficiency: Deficiency) -> Rgb {
+        let matrix = deficiency.matrix();
+        let rgb = self.to_linear();
+        let row = |i: usize| matrix[i][0] * rgb[0] + matrix[i][1] * rgb[1] + matrix[i][2] * rgb[2];
+        Rgb::from_linear([row(0), row(1), row(2)])
+    }
+
+    fn to_linear(self) -> [f64; 3] {
+        [self.r, self.g, self.b].map(|channel| {
+            let c = channel as f64 / 255.0;
+            match c <= 0.04045 {
+                true => c / 12.92,
+                false => ((c + 0.055) / 1.055).powf(2.4),
+            }
+        })
+    }
+
+    fn from_linear(rgb: [f64; 3]) -> Rgb {
+        let [r, g, b] = rgb.map(|c| {
+            let c = c.clamp(0.0, 1.0);
+            let c = match c <= 0.0031308 {
+                true => c * 12.92,
+                false => 1.055 * c.powf(1.0 / 2.4) - 0.055,
+            };
+            (c * 255.0).round() as u8
+        });
+        Rgb::new(r, g, b)
+    }
+}
+
+impl Display for Rgb {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02X}{:02X}{:02X}", self.r, self.g, self.b)
+    }
+}
+
+// Conversions from https://bottosson.github.io/posts/oklab/
+fn linear_to_oklab([r, g, b]: [f64; 3]) -> [f64; 3] {
+    let l = (0.4122214708 * r + 0.5363325363 * g + 0.0514459929 * b).cbrt();
+    let m = (0.2119034982 * r + 0.6806995451 * g + 0.1073969566 * b).cbrt();
+    let s = (0.0883024619 * r + 0.2817188376 * g + 0.6299787005 * b).cbrt();
+    [
+        0.2104542553 * l + 0.7936177850 * m - 0.0040720468 * s,
+        1.9779984951 * l - 2.4285922050 * m + 0.4505937099 * s,
+        0.0259040371 * l + 0.7827717662 * m - 0.8086757660 * s,
+    ]
+}
+
+fn oklab_to_linear([l, a, b]: [f64; 3]) -> [f64; 3] {
+    let l_ = (l + 0.3963377774 * a + 0.2158037573 * b).powi(3);
+    let m_ = (l - 0.1055613458 * a - 0.0638541728 * b).powi(3);
+    let s_ = (l - 0.0894841775 * a - 1.2914855480 * b).powi(3);
+    [
+        4.0767416621 * l_ - 3.3077115913 * m_ + 0.2309699292 * s_,
+        -1.2684380046 * l_ + 2.6097574011 * m_ - 0.3413193965 * s_,
+        -0.0041960863 * l_ - 0.7034186147 * m_ + 1.7076147010 * s_,
+    ]
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::Display, strum::EnumIter)]
+pub enum Deficiency {
+    // No functioning red cones.
+    #[strum(serialize = "protanopia")]
+    Protanopia,
+    // No functioning green cones, the most common form.
+    #[strum(serialize = "deuteranopia")]
+    Deuteranopia,
+    // No functioning blue cones.
+    #[strum(serialize = "tritanopia")]
+    Tritanopia,
+}
+
+impl Deficiency {
+    // Machado, Oliveira and Fernandes (2009), at full severity, on linear RGB
+    fn matrix(self) -> [[f64; 3]; 3] {
+        match self {
+            Deficiency::Protanopia => [
+                [0.152286, 1.052583, -0.204868],
+                [0.114503, 0.786281, 0.099216],
+                [-0.003882, -0.048116, 1.051998],
+            ],
+            Deficiency::Deuteranopia => [
+                [0.367322, 0.860646, -0.227968],
+                [0.280085, 0.672501, 0.047413],
+                [-0.011820, 0.042940, 0.968881],
+            ],
+            Deficiency::Tritanopia => [
+                [1.255528, -0.076749, -0.178779],
+                [-0.078411, 0.930809, 0.147602],
+                [0.004733, 0.691367, 0.303900],
+            ],
+        }
+    }
+}
+
+const OKABE_ITO: [&str; 8] = [
+    "#E69F00", "#56B4E9", "#009E73", "#F0E442", "#0072B2", "#D55E00", "#CC79A7", "#000000",
+];
+const VIRIDIS: [&str; 10] = [
+    "#440154", "#482878", "#3E4989", "#31688E", "#26828E", "#1F9E89", "#35B779", "#6DCD59",
+    "#B4DE2C", "#FDE725",
+];
+const SET1: [&str; 9] = [
+    "#E41A1C", "#377EB8", "#4DAF4A", "#984EA3", "#FF7F00", "#FFFF33", "#A65628", "#F781BF",
+    "#999999",
+];
+const SET2: [&str; 8] = [
+    "#66C2A5", "#FC8D62", "#8DA0CB", "#E78AC3", "#A6D854", "#FFD92F", "#E5C494", "#B3B3B3",
+];
+const DARK2: [&str; 8] = [
+    "#1B9E77", "#D95F02", "#7570B3", "#E7298A", "#66A61E", "#E6AB02", "#A6761D", "#666666",
+];
+const BLUES: [&str; 9] = [
+    "#F7FBFF", "#DEEBF7", "#C6DBEF", "#9ECAE1", "#6BAED6", "#4292C6", "#2171B5", "#08519C",
+    "#08306B",
+];
+const YL_OR_RD: [&str; 9] = [
+    "#FFFFCC", "#FFEDA0", "#FED976", "#FEB24C", "#FD8D3C", "#FC4E2A", "#E31A1C", "#BD0026",
+    "#800026",
+];
+const RD_YL_GN: [&str; 11] = [
+    "#A50026", "#D73027", "#F46D43", "#FDAE61", "#FEE08B", "#FFFFBF", "#D9EF8B", "#A6D96A",
+    "#66BD63", "#1A9850", "#006837",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, strum::Display, strum::EnumIter)]
+pub enum Palette {
+    // Qualitative, designed to stay distinct with colour-vision deficiencies.
+    #[strum(serialize = "okabe-ito")]
+    OkabeIto,
+    // Sequential, perceptually uniform and colour-blind safe.
+    #[strum(serialize = "viridis")]
+    Viridis,
+    // ColorBrewer qualitative sets.
+    #[strum(serialize = "set1")]
+    Set1,
+    #[strum(serialize = "set2")]
+    Set2,
+    #[strum(serialize = "dark2")]
+    Dark2,
+    // ColorBrewer sequential schemes.
+    #[strum(serialize = "blues")]
+    Blues,
+    #[strum(serialize = "ylorrd")]
+    YlOrRd,
+    // ColorBrewer diverging scheme, from red to green.
+    #[strum(serialize = "rdylgn")]
+    RdYlGn,
+}
+
+impl Palette {
+    pub fn named(name: &str) -> Result<Palette, PaletteError> {
+        use strum::IntoEnumIterator;
+        Palette::iter()
+            .find(|palette| palette.to_string() == name.to_ascii_lowercase())
+            .ok_or_else(|| PaletteError::UnknownPalette(name.to_string()))
+    }
+
+    /// Whether the colours are ordered, so that in-between colours can be interpolated.
+    pub fn is_sequential(self) -> bool {
+        matches!(
+            self,
+            Palette::Viridis | Palette::Blues | Palette::YlOrRd | Palette::RdYlGn
+        )
+    }
+
+    /// The colours that define the palette.
+    pub fn stops(self) -> Vec<Rgb> {
+        let hex: &[&str] = match self {
+            Palette::OkabeIto => &OKABE_ITO,
+            Palette::Viridis => &VIRIDIS,
+            Palette::Set1 => &SET1,
+            Palette::Set2 => &SET2,
+            Palette::Dark2 => &DARK2,
+            Palette::Blues => &BLUES,
+            Palette::YlOrRd => &YL_OR_RD,
+            Palette::RdYlGn => &RD_YL_GN,
+        };
+        hex.iter()
+            .map(|hex| Rgb::from_hex(hex).expect("built-in palettes are valid"))
+            .collect()
+    }
+
+    /// `n` colours from the palette. Sequential palettes are sampled evenly from end to end;
+    /// qualitative ones are repeated if they have fewer than `n` colours.
+    pub fn colors(self, n: usize) -> Vec<Rgb> {
+        let stops = self.stops();
+        match self.is_sequential() {
+            true => (0..n)
+                .map(|i| match n {
+                    1 => interpolate(&stops, 0.5),
+                    _ => interpolate(&stops, i as f64 / (n - 1) as f64),
+                })
+                .collect(),
+            false => stops.iter().copied().cycle().take(n).collect(),
+        }
+    }
+
+    /// Like [`Palette::colors`], as `#RRGGBB` strings, e.g. for [`crate::theme::Theme`].
+    pub fn hex(self, n: usize) -> Vec<String> {
+        self.colors(n).iter().map(Rgb::to_string).collect()
+    }
+}
+
+/// Colour at `t`, from 0 to 1, along evenly spaced `stops`, interpolated in Oklab so that the
+/// gradient looks even and doesn't pass through muddy in-between colours.
+pub fn interpolate(stops: &[Rgb], t: f64) -> Rgb {
+    match stops {
+        [] => Rgb::new(0, 0, 0),
+        [only] => *only,
+        _ => {
+            let position = t.clamp(0.0, 1.0) * (stops.len() - 1) as f64;
+            let index = (position.floor() as usize).min(stops.len() - 2);
+            let fraction = position - index as f64;
+            let (from, to) = (stops[index].to_oklab(), stops[index + 1].to_oklab());
+            Rgb::from_oklab([0, 1, 2].map(|i| from[i] + (to[i] - from[i]) * fraction))
+        }
+    }
+}
+
+/// `n` colours chosen to be as far apart from each other, and from a white background, as
+/// possible: each is the sRGB colour furthest in Oklab from those picked before it.
+pub fn distinct_colors(n: usize) -> Vec<Rgb> {
+    // Every 17th level of each channel, which includes all the #RGB shorthand colours
+    let levels: Vec<u8> = (0..=15).map(|level| level * 17).collect();
+    let candidates: Vec<[f64; 3]> = levels
+        .iter()
+        .flat_map(|&r| {
+            levels.iter().flat_map({
+                let levels = &levels;
+                move |&g| levels.iter().map(move |&b| Rgb::new(r, g, b).to_oklab())
+            })
+        })
+        .collect();
+
+    let white = Rgb::new(255, 255, 255).to_oklab();
+    let distance = |a: &[f64; 3], b: &[f64; 3]| {
+        ((a[0] - b[0]).powi(2) + (a[1] - b[1]).powi(2) + (a[2] - b[2]).powi(2)).sqrt()
+    };
+    // Distance from each candidate to the nearest colour picked so far
+    let mut nearest: Vec<f64> = candidates.iter().map(|c| distance(c, &white)).collect();
+    let mut colors = Vec::new();
+
+    for _ in 0..n {
+        let (best, _) =
+            nearest
+                .iter()
+                .enumerate()
+                .fold((0, f64::NEG_INFINITY), |(best, max), (i, &d)| {
+                    match d > max {
+                        true => (i, d),
+                        false => (best, max),
+                    }
+                });
+        let picked = candidates[best];
+        colors.push(Rgb::from_oklab(picked));
+        for (candidate, d) in candidates.iter().zip(nearest.iter_mut()) {
+            *d = d.min(distance(candidate, &picked));
+        }
+    }
+
+    colors
+}
+
+// Closer than this, two colours are hard to tell apart as categories
+const MIN_DISTINCT_DISTANCE: f64 = 0.07;
+
+/// Two neighbouring colours that look alike to someone with a colour-vision deficiency.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaletteWarning {
+    /// Index of the first of the two colours.
+    pub index: usize,
+    pub colors: (Rgb, Rgb),
+    pub deficiency: Deficiency,
+    pub distance: f64,
+}
+
+impl Display for PaletteWarning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "colours {} ({}) and {} ({}) are hard to tell apart with {} (distance {:.3})",
+            self.index,
+            self.colors.0,
+            self.index + 1,
+            self.colors.1,
+            self.deficiency,
+            self.distance
+        )
+    }
+}
+
+/// Lists the adjacent pairs of `colors` (e.g. neighbouring categories in a legend or stack)
+/// that become indistinguishable under protanopia, deuteranopia or tritanopia.
+pub fn check_adjacent(colors: &[Rgb]) -> Vec<PaletteWarning> {
+    use strum::IntoEnumIterator;
+
+    let mut warnings = Vec::new();
+    for (index, pair) in colors.windows(2).enumerate() {
+        for deficiency in Deficiency::iter() {
+            let distance = pair[0]
+                .simulate(deficiency)
+                .distance(pair[1].simulate(deficiency));
+            if distance < MIN_DISTINCT_DISTANCE {
+                warnings.push(PaletteWarning {
+                    index,
+                    colors: (pair[0], pair[1]),
+                    deficiency,
+                    distance,
+                });
+            }
+        }
+    }
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn palettes_interpolate_and_warn() {
+        let red = Rgb::from_hex("#D73027").unwrap();
+        assert_eq!(red.to_string(), "#D73027");
+        assert_eq!(Rgb::from_hex("#0F08").unwrap(), Rgb::new(0, 255, 0));
+        assert!(Rgb::from_hex("D73027").is_err());
+        assert_eq!(Rgb::from_oklab(red.to_oklab()), red);
+
+        // Halfway in lightness between black and white is a mid grey, not sRGB's #808080
+        let black_to_white = [Rgb::new(0, 0, 0), Rgb::new(255, 255, 255)];
+        assert_eq!(interpolate(&black_to_white, 0.5).to_string(), "#636363");
+
+        assert_eq!(Palette::named("Viridis").unwrap(), Palette::Viridis);
+        let viridis = Palette::Viridis.colors(3);
+        assert_eq!(viridis[0].to_string(), "#440154");
+        assert_eq!(viridis[2].to_string(), "#FDE725");
+        assert_eq!(Palette::Set2.colors(9)[8], Palette::Set2.colors(1)[0]);
+
+        let distinct = distinct_colors(6);
+        assert_eq!(distinct, distinct_colors(6));
+        for (i, a) in distinct.iter().enumerate() {
+            for b in &distinct[i + 1..] {
+                assert!(a.distance(*b) > 0.2);
+            }
+        }
+
+        // Okabe-Ito stays distinct; a red next to a green of the same lightness does not
+        assert!(check_adjacent(&Palette::OkabeIto.colors(8)).is_empty());
+        let green = Rgb::from_hex("#1A9850").unwrap();
+        let warnings = check_adjacent(&[red, green]);
+        assert!(warnings
+            .iter()
+            .any(|warning| warning.deficiency == Deficiency::Deuteranopia));
+    }
+}
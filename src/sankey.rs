@@ -2,6 +2,7 @@ use itertools::{Itertools, Permutations};
 use petgraph::stable_graph::{EdgeIndex, NodeIndex};
 use petgraph::visit::{EdgeRef, Topo, Walker};
 use petgraph::Directed;
+use serde::{Deserialize, Serialize};
 use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt::{Debug, Display};
 use std::iter::Iterator;
@@ -155,6 +156,8 @@ impl<N: Clone + Display, E: Clone> SankeySolver<N, E> {
 
 // type NodeCoordinates = HashMap<NodeIndex, IVec2>;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
 pub enum LayerOrderingMethod {
     // Order nodes in a layer by the average position of their neighbors in the previous layer.
     Barycenter,
@@ -167,14 +170,15 @@ type LayerId = usize;
 #[derive(Debug, Default)]
 pub struct SankeyLayers<N: Clone + Display, E: Clone> {
     graph: petgraph::Graph<N, E>,
-    pub layer_ids: HashMap<NodeIndex, LayerId>,
+    // Ordered by node, so that nodes within a layer are collected in insertion order
+    pub layer_ids: BTreeMap<NodeIndex, LayerId>,
 }
 impl<N: Clone + Display, E: Clone> SankeyLayers<N, E> {
     //The layer assignment step can be likened to creating a topological sort of the nodes in the graph,
     //but with additional constraints to create distinct layers. Each layer can be thought of as a set of
     //nodes that don't have any directed edges between them.
     pub fn new(graph: &petgraph::Graph<N, E>) -> Self {
-        let mut layer_ids: HashMap<NodeIndex, LayerId> = HashMap::new();
+        let mut layer_ids: BTreeMap<NodeIndex, LayerId> = BTreeMap::new();
 
         // let order = match toposort(graph, None) {
         //     Ok(order) => order,
@@ -278,7 +282,7 @@ mod tests {
         let layers = sankey.collect_by_layer();
 
         assert_eq!(layers.get(&0).unwrap(), &vec![n0]);
-        assert_eq!(layers.get(&1).unwrap(), &vec![n1, n2]); // Order might differ; we just want to check membership
+        assert_eq!(layers.get(&1).unwrap(), &vec![n1, n2]);
     }
     #[test]
     fn cycle_handling() {
@@ -1,15 +1,140 @@
+use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::Display;
 
 use petgraph::graph::{Graph, NodeIndex};
 use petgraph::{Directed, Direction};
+use serde::{Deserialize, Serialize};
 use svg::{
     node::{
         self,
-        element::{path, Element, Group, Path, Rectangle, Style, Text, SVG},
+        element::{
+            path, Definitions, Element, Group, LinearGradient, Path, Rectangle, Stop, Style, Text,
+            SVG,
+        },
     },
     Node,
 };
 
+use crate::palette::{self, Rgb};
+use crate::sankey::LayerOrderingMethod;
+use crate::theme::Theme;
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SankeyOrientation {
+    LeftToRight,
+    RightToLeft,
+    // Layers are stacked from the top, which suits narrow pages.
+    TopToBottom,
+}
+
+impl SankeyOrientation {
+    /// Splits a drawing's size into its length along the flow and its breadth across it.
+    pub fn flow_size(self, width: f64, height: f64) -> (f64, f64) {
+        match self {
+            SankeyOrientation::LeftToRight | SankeyOrientation::RightToLeft => (width, height),
+            SankeyOrientation::TopToBottom => (height, width),
+        }
+    }
+
+    /// Maps a point from flow coordinates, where the flow runs left to right along `x` over a
+    /// total `length`, to drawing coordinates.
+    pub fn transform(self, (x, y): (f64, f64), length: f64) -> (f64, f64) {
+        match self {
+            SankeyOrientation::LeftToRight => (x, y),
+            SankeyOrientation::RightToLeft => (length - x, y),
+            SankeyOrientation::TopToBottom => (y, x),
+        }
+    }
+
+    /// Maps a rectangle from flow coordinates to drawing coordinates, as `(x, y, width, height)`.
+    pub fn transform_rect(
+        self,
+        (x, y, width, height): (f64, f64, f64, f64),
+        length: f64,
+    ) -> (f64, f64, f64, f64) {
+        let (x1, y1) = self.transform((x, y), length);
+        let (x2, y2) = self.transform((x + width, y + height), length);
+        (x1.min(x2), y1.min(y2), (x2 - x1).abs(), (y2 - y1).abs())
+    }
+
+    // Text anchor for a label that extends along the flow, backwards or forwards from its point.
+    fn anchor(self, forwards: bool) -> &'static str {
+        match (self, forwards) {
+            (SankeyOrientation::TopToBottom, _) => "middle",
+            (SankeyOrientation::LeftToRight, true) | (SankeyOrientation::RightToLeft, false) => {
+                "start"
+            }
+            _ => "end",
+        }
+    }
+}
+
+/// Colours for an ordered state dimension, read from the node metadata entry `name` (see
+/// [`Sankey::set_node_metadata`]). Its `levels`, lowest first, are coloured evenly along the
+/// `colors` gradient, by default yellow through orange to dark red.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OrdinalScale {
+    pub name: String,
+    pub levels: Vec<String>,
+    pub colors: Vec<Rgb>,
+    /// Whether ribbons without a colour of their own take the colour of their target's level.
+    pub color_ribbons: bool,
+}
+
+impl OrdinalScale {
+    pub fn new(name: &str, levels: Vec<String>) -> OrdinalScale {
+        OrdinalScale {
+            name: name.to_string(),
+            levels,
+            // ColorBrewer YlOrRd, without its near-white end so that the lowest level shows on a
+            // light background. Lightness rises steadily along it, so neighbouring levels stay
+            // apart with colour-vision deficiencies.
+            colors: palette::Palette::YlOrRd.stops()[2..].to_vec(),
+            color_ribbons: false,
+        }
+    }
+
+    /// Neighbouring levels that are hard to tell apart with a colour-vision deficiency.
+    pub fn warnings(&self) -> Vec<palette::PaletteWarning> {
+        palette::check_adjacent(&self.level_colors())
+    }
+
+    /// The colour of every level, in order.
+    pub fn level_colors(&self) -> Vec<Rgb> {
+        (0..self.levels.len())
+            .map(|level| self.level_color(level))
+            .collect()
+    }
+
+    pub fn level_color(&self, level: usize) -> Rgb {
+        match self.levels.len() {
+            0 | 1 => palette::interpolate(&self.colors, 0.0),
+            n => palette::interpolate(&self.colors, level as f64 / (n - 1) as f64),
+        }
+    }
+
+    /// Colour of a node from its metadata, if it has a known level.
+    fn node_color(&self, node: &SankeyNode) -> Option<String> {
+        let value = node.metadata.get(&self.name)?;
+        let level = self.levels.iter().position(|level| level == value)?;
+        Some(self.level_color(level).to_string())
+    }
+}
+
+/// How nodes are assigned to layers.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum SankeyLayering {
+    // Each node one layer after its furthest predecessor, ignoring the edges that close cycles.
+    LongestPath,
+    // One layer per value of the named metadata entry (such as "dose"), in sorted order, so that
+    // states that no subject reached still line up with their step. Nodes without the entry keep
+    // their longest-path layer.
+    Metadata(String),
+}
+
+#[derive(Clone)]
 pub struct SankeyStyle<F: Fn(f64) -> String> {
     pub number_format: Option<F>,
     pub node_separation: Option<f64>,
@@ -18,6 +143,24 @@ pub struct SankeyStyle<F: Fn(f64) -> String> {
     pub font_size: Option<f64>,
     pub font_color: Option<String>,
     pub border: Option<f64>,
+    /// Pixels per unit of flow. By default, the largest scale at which the tallest layer fits.
+    pub scale: Option<f64>,
+    /// Whether to draw faded stubs for flow entering or leaving a node outside of any edge. Off by
+    /// default.
+    pub show_imbalance: Option<bool>,
+    pub label_imbalance: Option<bool>,
+    pub orientation: Option<SankeyOrientation>,
+    /// By default, longest-path layering.
+    pub layering: Option<SankeyLayering>,
+    /// How the nodes of each layer are ordered from top to bottom. By default, in insertion order.
+    pub ordering: Option<LayerOrderingMethod>,
+    /// Colours and fonts. The font settings above take precedence over the theme's.
+    pub theme: Option<Theme>,
+    /// Colours nodes (and optionally ribbons) by an ordered state such as the grade, and adds a
+    /// legend for it.
+    pub ordinal: Option<OrdinalScale>,
+    /// Prepended to every element id, so that several diagrams can share one document.
+    pub id_prefix: Option<String>,
 }
 
 impl<F: Fn(f64) -> String> Default for SankeyStyle<F> {
@@ -30,6 +173,15 @@ impl<F: Fn(f64) -> String> Default for SankeyStyle<F> {
             font_size: None,
             font_color: None,
             border: None,
+            scale: None,
+            show_imbalance: None,
+            label_imbalance: None,
+            orientation: None,
+            layering: None,
+            ordering: None,
+            theme: None,
+            ordinal: None,
+            id_prefix: None,
         }
     }
 }
@@ -37,6 +189,7 @@ impl<F: Fn(f64) -> String> Default for SankeyStyle<F> {
 pub struct Sankey {
     nodes: Vec<SankeyNode>,
     edges: Vec<SankeyEdge>,
+    keys: BTreeMap<String, SankeyNodeID>,
 }
 
 impl Sankey {
@@ -44,6 +197,7 @@ impl Sankey {
         Sankey {
             nodes: Vec::new(),
             edges: Vec::new(),
+            keys: BTreeMap::new(),
         }
     }
 
@@ -65,16 +219,28 @@ impl Sankey {
         value: f64,
         label: Option<String>,
         color: Option<String>,
-    ) {
+    ) -> SankeyEdgeID {
+        let id = self.edges.len();
         self.edges.push(SankeyEdge {
             source,
             target,
             value,
             label,
             color,
+            subjects: Vec::new(),
+            metadata: BTreeMap::new(),
         });
         self.nodes[source.0].current_output += value;
         self.nodes[target.0].current_input += value;
+        SankeyEdgeID(id)
+    }
+
+    pub fn add_subject(&mut self, edge: SankeyEdgeID, subject: String) {
+        self.edges[edge.0].subjects.push(subject);
+    }
+
+    pub fn subjects(&self, edge: SankeyEdgeID) -> &[String] {
+        &self.edges[edge.0].subjects
     }
 
     pub fn value(&self, node: SankeyNodeID) -> Option<f64> {
@@ -109,72 +275,331 @@ impl Sankey {
         self.nodes[node.0].flow()
     }
 
-    pub fn draw<F: Fn(f64) -> String>(
+    /// Inflow missing from the node's edges, drawn as a stub when `show_imbalance` is set.
+    pub fn unaccounted_input(&self, node: SankeyNodeID) -> f64 {
+        self.nodes[node.0].unaccounted_input()
+    }
+
+    /// Outflow leaving the diagram instead of following an edge, drawn as a stub when
+    /// `show_imbalance` is set.
+    pub fn unaccounted_output(&self, node: SankeyNodeID) -> f64 {
+        self.nodes[node.0].unaccounted_output()
+    }
+
+    pub fn node_ids(&self) -> impl Iterator<Item = SankeyNodeID> {
+        (0..self.nodes.len()).map(SankeyNodeID)
+    }
+
+    pub fn edge_ids(&self) -> impl Iterator<Item = SankeyEdgeID> {
+        (0..self.edges.len()).map(SankeyEdgeID)
+    }
+
+    pub fn node_label(&self, node: SankeyNodeID) -> Option<&str> {
+        self.nodes[node.0].label.as_deref()
+    }
+
+    pub fn node_color(&self, node: SankeyNodeID) -> Option<&str> {
+        self.nodes[node.0].color.as_deref()
+    }
+
+    /// Identifies the node by a key of the caller's choosing (e.g. the `DosageEvent` it was built
+    /// from), for [`Sankey::node_by_key`] and as the node's id in the SVG. Keys are unique: a key
+    /// given to a second node is taken away from the first.
+    pub fn set_node_key(&mut self, node: SankeyNodeID, key: String) {
+        if let Some(previous) = self.nodes[node.0].key.take() {
+            self.keys.remove(&previous);
+        }
+        if let Some(other) = self.keys.insert(key.clone(), node) {
+            self.nodes[other.0].key = None;
+        }
+        self.nodes[node.0].key = Some(key);
+    }
+
+    pub fn node_key(&self, node: SankeyNodeID) -> Option<&str> {
+        self.nodes[node.0].key.as_deref()
+    }
+
+    pub fn node_by_key(&self, key: &str) -> Option<SankeyNodeID> {
+        self.keys.get(key).copied()
+    }
+
+    /// Attaches a named value to the node, drawn as a `data-{name}` attribute on its rect.
+    pub fn set_node_metadata(&mut self, node: SankeyNodeID, name: String, value: String) {
+        self.nodes[node.0].metadata.insert(name, value);
+    }
+
+    pub fn node_metadata(&self, node: SankeyNodeID) -> &BTreeMap<String, String> {
+        &self.nodes[node.0].metadata
+    }
+
+    /// Attaches a named value to the edge, drawn as a `data-{name}` attribute on its group.
+    pub fn set_edge_metadata(&mut self, edge: SankeyEdgeID, name: String, value: String) {
+        self.edges[edge.0].metadata.insert(name, value);
+    }
+
+    pub fn edge_metadata(&self, edge: SankeyEdgeID) -> &BTreeMap<String, String> {
+        &self.edges[edge.0].metadata
+    }
+
+    pub fn source(&self, edge: SankeyEdgeID) -> SankeyNodeID {
+        self.edges[edge.0].source
+    }
+
+    pub fn target(&self, edge: SankeyEdgeID) -> SankeyNodeID {
+        self.edges[edge.0].target
+    }
+
+    pub fn edge_value(&self, edge: SankeyEdgeID) -> f64 {
+        self.edges[edge.0].value
+    }
+
+    pub fn edge_label(&self, edge: SankeyEdgeID) -> Option<&str> {
+        self.edges[edge.0].label.as_deref()
+    }
+
+    pub fn edge_color(&self, edge: SankeyEdgeID) -> Option<&str> {
+        self.edges[edge.0].color.as_deref()
+    }
+
+    /// Lists the nodes with a declared value that their incoming or outgoing edges do not add up
+    /// to. Sources are not checked against their inputs, nor sinks against their outputs.
+    pub fn validate(&self) -> Vec<SankeyImbalance> {
+        self.nodes
+            .iter()
+            .enumerate()
+            .filter_map(|(id, node)| {
+                let value = node.value?;
+                let input_mismatch = node.current_input > 0.0
+                    && (value - node.current_input).abs() > IMBALANCE_TOLERANCE;
+                let output_mismatch = node.current_output > 0.0
+                    && (value - node.current_output).abs() > IMBALANCE_TOLERANCE;
+                (input_mismatch || output_mismatch).then(|| SankeyImbalance {
+                    node: SankeyNodeID(id),
+                    label: node.label.clone(),
+                    value,
+                    input: node.current_input,
+                    output: node.current_output,
+                })
+            })
+            .collect()
+    }
+
+    /// Scale, in pixels per unit of flow, that [`Sankey::draw`] will use for this style.
+    pub fn scale<F: Fn(f64) -> String>(
         &self,
         width: f64,
         height: f64,
-        style: SankeyStyle<F>,
-    ) -> SVG {
-        let node_separation = style.node_separation.unwrap_or(height / 30.0);
-        let node_width = style.node_width.unwrap_or(width / 100.0);
-        let font_family: String = style.font_family.unwrap_or("sans-serif".to_string());
-        let font_size: f64 = style.font_size.unwrap_or(height / 50.0);
-        let font_color: String = style.font_color.unwrap_or("#000".to_string());
-        let border: f64 = style.border.unwrap_or(height / 10.0);
+        style: &SankeyStyle<F>,
+    ) -> f64 {
+        style
+            .scale
+            .unwrap_or_else(|| self.fit_scale(width, height, style))
+    }
 
-        // Initialise SVG
+    /// Largest scale, in pixels per unit of flow, at which every layer fits across the drawing.
+    pub fn fit_scale<F: Fn(f64) -> String>(
+        &self,
+        width: f64,
+        height: f64,
+        style: &SankeyStyle<F>,
+    ) -> f64 {
+        let orientation = style.orientation.unwrap_or(SankeyOrientation::LeftToRight);
+        let (_, breadth) = orientation.flow_size(width, height);
+        let node_separation = style.node_separation.unwrap_or(breadth / 30.0);
+        let border: f64 = style.border.unwrap_or(breadth / 10.0);
 
-        let mut document = SVG::new();
+        let layers = self.layers(style.layering.as_ref(), style.ordering);
+        let loops = self.loops(&layers);
 
-        document.assign("viewBox", (0.0, 0.0, width, height));
+        self.layer_scale(&layers, &loops, breadth, border, node_separation)
+    }
 
-        document.append(Style::new(format!(
-            "rect.node {{
-	fill: #000F;
-}}
+    fn layer_scale(
+        &self,
+        layers: &[Vec<SankeyNodeID>],
+        loops: &[SankeyLoop],
+        height: f64,
+        border: f64,
+        node_separation: f64,
+    ) -> f64 {
+        // Loops run around the diagram, each in its own lane
+        let loop_value: f64 = loops.iter().map(|l| self.edges[l.edge.0].value).sum();
+        let loop_separation = node_separation / 2.0 * loops.len() as f64;
 
-.edge > path {{
-	fill: #0004;
-}}
+        let mut min_scale = f64::INFINITY;
 
-text.node, .edge > text {{
-	fill: {font_color};
-	text-anchor: middle;
-	dominant-baseline: central;
-	font-family: {font_family};
-	font-size: {font_size}px;
-}}
+        for layer in layers {
+            let total_value: f64 = layer.iter().map(|node| self.nodes[node.0].flow()).sum();
+            let scale = (height
+                - border * 2.0
+                - node_separation * ((layer.len() - 1) as f64)
+                - loop_separation)
+                / (total_value + loop_value);
+            if scale < min_scale {
+                min_scale = scale;
+            }
+        }
 
-.edge:not(:hover) > text {{
-	display: none;
-}}"
-        )));
+        min_scale
+    }
+
+    // Flags the edges that would close a cycle, found as the back edges of a depth-first search
+    // from each node in insertion order. Self-loops are always flagged.
+    fn circular_edges(&self) -> Vec<bool> {
+        let mut outputs = vec![Vec::new(); self.nodes.len()];
+        for (id, edge) in self.edges.iter().enumerate() {
+            outputs[edge.source.0].push(id);
+        }
 
-        // Pre-process graph
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            New,
+            OnStack,
+            Done,
+        }
+
+        let mut circular = vec![false; self.edges.len()];
+        let mut visits = vec![Visit::New; self.nodes.len()];
+
+        for root in 0..self.nodes.len() {
+            if visits[root] != Visit::New {
+                continue;
+            }
+            visits[root] = Visit::OnStack;
+            // (node, index of the next output to follow)
+            let mut stack = vec![(root, 0)];
+
+            while let Some((node, next)) = stack.last_mut() {
+                let node = *node;
+                match outputs[node].get(*next) {
+                    Some(&edge) => {
+                        *next += 1;
+                        let target = self.edges[edge].target.0;
+                        match visits[target] {
+                            Visit::New => {
+                                visits[target] = Visit::OnStack;
+                                stack.push((target, 0));
+                            }
+                            Visit::OnStack => circular[edge] = true,
+                            Visit::Done => {}
+                        }
+                    }
+                    None => {
+                        visits[node] = Visit::Done;
+                        stack.pop();
+                    }
+                }
+            }
+        }
 
-        #[derive(Copy, Clone, Debug)]
-        struct SankeyEdgeID(usize);
+        circular
+    }
+
+    fn layers(
+        &self,
+        layering: Option<&SankeyLayering>,
+        ordering: Option<LayerOrderingMethod>,
+    ) -> Vec<Vec<SankeyNodeID>> {
+        let circular = self.circular_edges();
+        let mut layers = self.longest_path_layers(&circular);
+
+        if let Some(SankeyLayering::Metadata(name)) = layering {
+            let values: BTreeSet<&String> = self
+                .nodes
+                .iter()
+                .filter_map(|node| node.metadata.get(name))
+                .collect();
+            let rank = |value: &String| values.iter().position(|&v| v == value).unwrap();
+
+            let mut pinned = vec![Vec::new(); values.len().max(layers.len())];
+            for (layer, nodes) in layers.iter().enumerate() {
+                for &node in nodes {
+                    match self.nodes[node.0].metadata.get(name) {
+                        Some(value) => pinned[rank(value)].push(node),
+                        None => pinned[layer].push(node),
+                    }
+                }
+            }
+            pinned.retain(|layer| !layer.is_empty());
+            layers = pinned;
+        }
+
+        if let Some(method) = ordering {
+            self.order_layers(&mut layers, &circular, method);
+        }
+
+        layers
+    }
+
+    // Sorts each layer, from the first, by the position of its nodes' sources in the layers
+    // already sorted, weighted by the value of the edges. Positions are relative to the size of
+    // their layer, and a node without sorted sources keeps its own position.
+    fn order_layers(
+        &self,
+        layers: &mut [Vec<SankeyNodeID>],
+        circular: &[bool],
+        method: LayerOrderingMethod,
+    ) {
+        let mut position: Vec<Option<f64>> = vec![None; self.nodes.len()];
+
+        for layer in layers.iter_mut() {
+            let count = layer.len() as f64;
+            let mut keys = vec![0.0; self.nodes.len()];
+
+            for (index, &node) in layer.iter().enumerate() {
+                let mut sources: Vec<(f64, f64)> = self
+                    .edges
+                    .iter()
+                    .enumerate()
+                    .filter(|&(id, edge)| !circular[id] && edge.target == node)
+                    .filter_map(|(_, edge)| Some((position[edge.source.0]?, edge.value)))
+                    .collect();
+
+                keys[node.0] = match sources.is_empty() {
+                    true => (index as f64 + 0.5) / count,
+                    false => match method {
+                        LayerOrderingMethod::Barycenter => {
+                            let total: f64 = sources.iter().map(|&(_, value)| value).sum();
+                            sources.iter().map(|&(at, value)| at * value).sum::<f64>() / total
+                        }
+                        LayerOrderingMethod::Median => {
+                            sources.sort_by(|a, b| a.0.total_cmp(&b.0));
+                            let half: f64 =
+                                sources.iter().map(|&(_, value)| value).sum::<f64>() / 2.0;
+                            let mut total = 0.0;
+                            sources
+                                .iter()
+                                .find(|&&(_, value)| {
+                                    total += value;
+                                    total >= half
+                                })
+                                .map_or(0.0, |&(at, _)| at)
+                        }
+                    },
+                };
+            }
 
-        #[derive(Clone, Debug)]
-        struct Dependencies {
-            inputs: Vec<SankeyEdgeID>,
-            outputs: Vec<SankeyEdgeID>,
+            // Stable, so that ties keep their order
+            layer.sort_by(|a, b| keys[a.0].total_cmp(&keys[b.0]));
+            for (index, node) in layer.iter().enumerate() {
+                position[node.0] = Some((index as f64 + 0.5) / count);
+            }
         }
+    }
+
+    // Each node is placed one layer after its furthest input.
+    fn longest_path_layers(&self, circular: &[bool]) -> Vec<Vec<SankeyNodeID>> {
+        // Pre-process graph, leaving out the edges that close cycles
 
         let mut dependency_counts = vec![0; self.nodes.len()];
-        let mut node_edges = vec![
-            Dependencies {
-                inputs: Vec::new(),
-                outputs: Vec::new()
-            };
-            self.nodes.len()
-        ];
+        let mut outputs = vec![Vec::new(); self.nodes.len()];
         for (id, &SankeyEdge { source, target, .. }) in self.edges.iter().enumerate() {
-            node_edges[source.0].outputs.push(SankeyEdgeID(id));
-            node_edges[target.0].inputs.push(SankeyEdgeID(id));
+            if circular[id] {
+                continue;
+            }
+            outputs[source.0].push(SankeyEdgeID(id));
             dependency_counts[target.0] += 1;
         }
-        let node_edges = node_edges;
 
         // Split into layers
 
@@ -187,19 +612,13 @@ text.node, .edge > text {{
             }
         }
 
-        let mut min_scale = f64::INFINITY;
-
         while !next_layer.is_empty() {
             layers.push(next_layer);
             next_layer = Vec::new();
             let current_layer = layers.last().unwrap();
 
-            let mut total_value = 0.0;
-
             for node_id in current_layer {
-                let node = &self.nodes[node_id.0];
-                total_value += node.flow();
-                for edge in &node_edges[node_id.0].outputs {
+                for edge in &outputs[node_id.0] {
                     let target = self.edges[edge.0].target;
                     dependency_counts[target.0] -= 1;
                     if dependency_counts[target.0] == 0 {
@@ -207,15 +626,229 @@ text.node, .edge > text {{
                     }
                 }
             }
+        }
 
-            let scale =
-                (height - border * 2.0 - node_separation * ((current_layer.len() - 1) as f64))
-                    / total_value;
-            if scale < min_scale {
-                min_scale = scale;
+        layers
+    }
+
+    // Deterministic SVG ids: the name of each node (its key, or its index if it has none), the
+    // node ids, and the edge ids, made of their endpoints' names and, for an edge with a single
+    // subject, that subject. Ids that would repeat are numbered, and all start with `prefix`.
+    fn svg_ids(&self, prefix: &str) -> (Vec<String>, Vec<String>, Vec<String>) {
+        let mut used = BTreeSet::new();
+        let mut unique = |id: String| {
+            let mut candidate = id.clone();
+            let mut count = 1;
+            while !used.insert(candidate.clone()) {
+                count += 1;
+                candidate = format!("{id}-{count}");
+            }
+            candidate
+        };
+
+        let node_names: Vec<String> = self
+            .nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| {
+                let name = node.key.as_deref().map(svg_name).unwrap_or_default();
+                match name.is_empty() {
+                    true => index.to_string(),
+                    false => name,
+                }
+            })
+            .collect();
+
+        let node_ids = node_names
+            .iter()
+            .map(|name| unique(format!("{prefix}node-{name}")))
+            .collect();
+
+        let edge_ids = self
+            .edges
+            .iter()
+            .map(|edge| {
+                let mut id = format!(
+                    "{prefix}edge-{}--{}",
+                    node_names[edge.source.0], node_names[edge.target.0]
+                );
+                if let [subject] = edge.subjects.as_slice() {
+                    id.push_str(&format!("-subj-{}", svg_name(subject)));
+                }
+                unique(id)
+            })
+            .collect();
+
+        (node_names, node_ids, edge_ids)
+    }
+
+    // Backward edges and self-loops, with the side of the diagram they are routed around: above
+    // for sources in the upper half of their layer, below otherwise. Loops are sorted from the
+    // innermost lane outwards, shorter loops first.
+    fn loops(&self, layers: &[Vec<SankeyNodeID>]) -> Vec<SankeyLoop> {
+        let mut placement = vec![(0, 0, 1); self.nodes.len()];
+        for (layer_index, layer) in layers.iter().enumerate() {
+            for (rank, node) in layer.iter().enumerate() {
+                placement[node.0] = (layer_index, rank, layer.len());
             }
         }
 
+        let mut loops: Vec<SankeyLoop> = self
+            .circular_edges()
+            .into_iter()
+            .enumerate()
+            .filter(|&(_, circular)| circular)
+            .map(|(id, _)| {
+                let edge = &self.edges[id];
+                let (source_layer, rank, layer_size) = placement[edge.source.0];
+                let (target_layer, ..) = placement[edge.target.0];
+                SankeyLoop {
+                    edge: SankeyEdgeID(id),
+                    above: rank * 2 < layer_size,
+                    span: source_layer.abs_diff(target_layer),
+                }
+            })
+            .collect();
+        loops.sort_by_key(|l| (l.span, l.edge.0));
+
+        loops
+    }
+
+    pub fn draw<F: Fn(f64) -> String>(
+        &self,
+        width: f64,
+        height: f64,
+        style: SankeyStyle<F>,
+    ) -> SVG {
+        let orientation = style.orientation.unwrap_or(SankeyOrientation::LeftToRight);
+        // Nodes are laid out in flow coordinates, running left to right, and only transformed
+        // into drawing coordinates as they are emitted
+        let (length, breadth) = orientation.flow_size(width, height);
+        let at = |point: (f64, f64)| orientation.transform(point, length);
+
+        let node_separation = style.node_separation.unwrap_or(breadth / 30.0);
+        let node_width = style.node_width.unwrap_or(length / 100.0);
+        let theme = style.theme.unwrap_or_default();
+        let font_family: String = style.font_family.unwrap_or(theme.font_family.clone());
+        let font_size: f64 = style.font_size.unwrap_or(breadth / 50.0);
+        let font_color: String = style.font_color.unwrap_or(theme.font_color.clone());
+        let border: f64 = style.border.unwrap_or(breadth / 10.0);
+        let show_imbalance = style.show_imbalance.unwrap_or(false);
+        let label_imbalance = style.label_imbalance.unwrap_or(false);
+        let ordinal = style.ordinal.as_ref();
+        let id_prefix = style.id_prefix.as_deref().unwrap_or_default();
+
+        // Initialise SVG
+
+        let mut document = SVG::new();
+
+        document.assign("viewBox", (0.0, 0.0, width, height));
+
+        let node_fill = &theme.node_fill;
+        let node_stroke = theme.node_stroke.as_deref().unwrap_or("none");
+        let ribbon_color = &theme.ribbon_color;
+        let ribbon_opacity = theme.ribbon_opacity;
+
+        document.append(Style::new(format!(
+            "rect.node {{
+	fill: {node_fill};
+	stroke: {node_stroke};
+}}
+
+.edge > path {{
+	fill: {ribbon_color};
+	fill-opacity: {ribbon_opacity};
+}}
+
+text.node, .edge > text {{
+	fill: {font_color};
+	text-anchor: middle;
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}
+
+.edge.circular > path {{
+	fill: none;
+	stroke: {ribbon_color};
+	stroke-opacity: {ribbon_opacity};
+	stroke-linejoin: round;
+}}
+
+.edge:not(:hover) > text {{
+	display: none;
+}}
+
+path.imbalance.input {{
+	fill: url(#{id_prefix}imbalance-input);
+}}
+
+path.imbalance.output {{
+	fill: url(#{id_prefix}imbalance-output);
+}}
+
+text.imbalance, text.legend {{
+	fill: {font_color};
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}"
+        )));
+
+        if let Some(background) = theme.background.as_deref() {
+            let mut rect = Rectangle::new();
+            rect.assign("x", 0.0);
+            rect.assign("y", 0.0);
+            rect.assign("width", width);
+            rect.assign("height", height);
+            rect.assign("class", "background");
+            rect.assign("style", format!("fill:{background}"));
+            document.append(rect);
+        }
+
+        // Stubs fade out away from the node they belong to
+        let mut definitions = Definitions::new();
+        for (id, opacities) in [
+            ("imbalance-input", [0.0, ribbon_opacity]),
+            ("imbalance-output", [ribbon_opacity, 0.0]),
+        ] {
+            let mut gradient = LinearGradient::new();
+            gradient.assign("id", format!("{id_prefix}{id}"));
+            // Along the flow, in the stub's bounding box
+            let (x1, y1, x2, y2) = match orientation {
+                SankeyOrientation::LeftToRight => (0, 0, 1, 0),
+                SankeyOrientation::RightToLeft => (1, 0, 0, 0),
+                SankeyOrientation::TopToBottom => (0, 0, 0, 1),
+            };
+            gradient.assign("x1", x1);
+            gradient.assign("y1", y1);
+            gradient.assign("x2", x2);
+            gradient.assign("y2", y2);
+            for (offset, opacity) in [0, 1].into_iter().zip(opacities) {
+                gradient.append(
+                    Stop::new()
+                        .set("offset", offset)
+                        .set("stop-color", ribbon_color.as_str())
+                        .set("stop-opacity", opacity),
+                );
+            }
+            definitions.append(gradient);
+        }
+        document.append(definitions);
+
+        // Split into layers
+
+        let layers = self.layers(style.layering.as_ref(), style.ordering);
+        let loops = self.loops(&layers);
+
+        let min_scale = match style.scale {
+            Some(scale) => scale,
+            None => self.layer_scale(&layers, &loops, breadth, border, node_separation),
+        };
+
+        // Reported so that a scale bar can be drawn to match
+        document.assign("data-scale", min_scale);
+
         // Generate nodes
 
         let mut svg_nodes = Vec::new();
@@ -223,34 +856,99 @@ text.node, .edge > text {{
 
         let mut positions = vec![(0.0, 0.0, 0.0); self.nodes.len()];
 
-        let layer_width = (width - border * 2.0 - (layers.len() as f64) * node_width)
-            / ((layers.len() - 1) as f64);
+        // Offset of each loop's lane from the edge of the node region, innermost first
 
-        let mut x = border;
-        for layer in layers {
+        let loop_separation = node_separation / 2.0;
+        let mut lanes = vec![None; self.edges.len()];
+        let mut lane_space = [0.0, 0.0];
+        for (side, above) in [true, false].into_iter().enumerate() {
+            for l in loops.iter().filter(|l| l.above == above) {
+                lane_space[side] += loop_separation;
+                lanes[l.edge.0] = Some((above, lane_space[side]));
+                lane_space[side] += self.edges[l.edge.0].value * min_scale;
+            }
+        }
+
+        // Leave room for the loops around the nodes: above and below, and to the left of the
+        // first layer and to the right of the last
+        let region_top = border + lane_space[0];
+        let region_bottom = breadth - border - lane_space[1];
+        let loop_reach = |layer: Option<&Vec<SankeyNodeID>>,
+                          end: fn(&SankeyEdge) -> SankeyNodeID| {
+            loops
+                .iter()
+                .filter(|l| layer.is_some_and(|layer| layer.contains(&end(&self.edges[l.edge.0]))))
+                .filter_map(|l| {
+                    lanes[l.edge.0]
+                        .map(|(_, offset)| offset + self.edges[l.edge.0].value * min_scale)
+                })
+                .fold(0.0, f64::max)
+        };
+        let left_space = loop_reach(layers.first(), |edge| edge.target);
+        let right_space = loop_reach(layers.last(), |edge| edge.source);
+
+        let layer_width =
+            (length - border * 2.0 - left_space - right_space - (layers.len() as f64) * node_width)
+                / ((layers.len() - 1) as f64);
+
+        let (node_names, node_ids, edge_ids) = self.svg_ids(id_prefix);
+        let mut node_layers = vec![0; self.nodes.len()];
+
+        let mut x = border + left_space;
+        for (layer_index, layer) in layers.into_iter().enumerate() {
             let mut total_height = -node_separation;
             for node_id in &layer {
                 total_height += self.nodes[node_id.0].flow() * min_scale + node_separation;
             }
             let total_height = total_height;
-            let mut y = (height - total_height) / 2.0;
+            let mut y = (region_top + region_bottom - total_height) / 2.0;
             for node_id in &layer {
                 let node = &self.nodes[node_id.0];
                 positions[node_id.0] = (x, y, y);
+                node_layers[node_id.0] = layer_index;
+
+                // One class per layer and per state (e.g. "grade-G1"), for stylesheets to target
+                let mut classes = vec!["node".to_string(), format!("layer-{layer_index}")];
+                classes.extend(node.metadata.iter().map(|(name, value)| {
+                    format!(
+                        "{}-{}",
+                        svg_name(name).to_ascii_lowercase(),
+                        svg_name(value)
+                    )
+                }));
+
+                let (rect_x, rect_y, rect_width, rect_height) =
+                    orientation.transform_rect((x, y, node_width, node.flow() * min_scale), length);
 
                 let mut rect = Rectangle::new();
-                rect.assign("x", x);
-                rect.assign("y", y);
-                rect.assign("width", node_width);
-                rect.assign("height", node.flow() * min_scale);
-                rect.assign("class", "node");
-                if let Some(color) = node.color.as_deref() {
+                rect.assign("x", rect_x);
+                rect.assign("y", rect_y);
+                rect.assign("width", rect_width);
+                rect.assign("height", rect_height);
+                rect.assign("id", node_ids[node_id.0].as_str());
+                rect.assign("class", classes.join(" "));
+                rect.assign("data-node", node_id.0);
+                if let Some(label) = &node.label {
+                    rect.assign("data-label", label.as_str());
+                }
+                if let Some(key) = &node.key {
+                    rect.assign("data-key", key.as_str());
+                }
+                for (name, value) in &node.metadata {
+                    rect.assign(data_attribute(name), value.as_str());
+                }
+                let ordinal_color = ordinal.and_then(|scale| scale.node_color(node));
+                if let Some(color) = node
+                    .color
+                    .as_deref()
+                    .or(ordinal_color.as_deref())
+                    .or(theme.palette_color(node_id.0))
+                {
                     rect.assign("style", format!("fill:{color}"));
                 }
                 svg_nodes.push(rect);
 
-                let mid_x = x + node_width / 2.0;
-                let mid_y = y + node.flow() * min_scale / 2.0;
+                let (mid_x, mid_y) = at((x + node_width / 2.0, y + node.flow() * min_scale / 2.0));
 
                 let mut text = Text::new();
                 text.assign("x", mid_x);
@@ -285,7 +983,17 @@ text.node, .edge > text {{
 
         let mut svg_edges = Vec::new();
 
-        for edge in &self.edges {
+        // Loops above take the top of each node's sides, innermost first so that lanes do not
+        // cross, and loops below take the bottom, innermost last
+        let mut edge_order: Vec<usize> =
+            loops.iter().filter(|l| l.above).map(|l| l.edge.0).collect();
+        edge_order.extend((0..self.edges.len()).filter(|&id| lanes[id].is_none()));
+        edge_order.extend(loops.iter().rev().filter(|l| !l.above).map(|l| l.edge.0));
+
+        for (id, edge, lane) in edge_order
+            .into_iter()
+            .map(|id| (id, &self.edges[id], lanes[id]))
+        {
             let thickness = edge.value * min_scale;
             let from_x = positions[edge.source.0].0 + node_width;
             let from_y_start = positions[edge.source.0].2;
@@ -294,44 +1002,112 @@ text.node, .edge > text {{
             let to_y_start = positions[edge.target.0].1;
             let to_y_end = to_y_start + thickness;
             let mid_x = (from_x + to_x) / 2.0;
-            let mid_y = (from_y_start + to_y_end) / 2.0;
 
             positions[edge.source.0].2 = from_y_end;
             positions[edge.target.0].1 = to_y_end;
 
+            // Classes for the layer the edge leaves from, its endpoints and its subjects
+            let mut classes = vec!["edge".to_string()];
+            if lane.is_some() {
+                classes.push("circular".to_string());
+            }
+            classes.push(format!("layer-{}", node_layers[edge.source.0]));
+            classes.push(format!("from-{}", node_names[edge.source.0]));
+            classes.push(format!("to-{}", node_names[edge.target.0]));
+            classes.extend(
+                edge.subjects
+                    .iter()
+                    .map(|subject| format!("subject-{}", svg_name(subject))),
+            );
+
             let mut group = Group::new();
-            group.assign("class", "edge");
+            group.assign("id", edge_ids[id].as_str());
+            group.assign("class", classes.join(" "));
+            group.assign("data-source", edge.source.0);
+            group.assign("data-target", edge.target.0);
+            group.assign("data-value", edge.value);
+            if !edge.subjects.is_empty() {
+                group.assign("data-subjects", edge.subjects.join(" "));
+            }
+            for (name, value) in &edge.metadata {
+                group.assign(data_attribute(name), value.as_str());
+            }
+
+            let edge_color = edge.color.clone().or_else(|| {
+                ordinal
+                    .filter(|scale| scale.color_ribbons)
+                    .and_then(|scale| scale.node_color(&self.nodes[edge.target.0]))
+            });
 
             let mut path = Path::new();
-            path.assign(
-                "d",
-                path::Data::new()
-                    .move_to((from_x, from_y_start))
-                    .cubic_curve_to((mid_x, from_y_start, mid_x, to_y_start, to_x, to_y_start))
-                    .line_to((to_x, to_y_end))
-                    .cubic_curve_to((mid_x, to_y_end, mid_x, from_y_end, from_x, from_y_end))
-                    .close(),
-            );
-            if let Some(color) = edge.color.as_deref() {
-                path.assign("style", format!("fill:{color}"));
-            }
+            let label_position = if let Some((above, offset)) = lane {
+                // Drawn as a thick line along the middle of the ribbon, which goes out of the
+                // source, around the nodes and back into the target
+                let lane_y = match above {
+                    true => region_top - offset - thickness / 2.0,
+                    false => region_bottom + offset + thickness / 2.0,
+                };
+                let reach = offset + thickness / 2.0;
+                let right_x = from_x + reach;
+                let left_x = to_x - reach;
+
+                path.assign(
+                    "d",
+                    path::Data::new()
+                        .move_to(at((from_x, from_y_start + thickness / 2.0)))
+                        .line_to(at((right_x, from_y_start + thickness / 2.0)))
+                        .line_to(at((right_x, lane_y)))
+                        .line_to(at((left_x, lane_y)))
+                        .line_to(at((left_x, to_y_start + thickness / 2.0)))
+                        .line_to(at((to_x, to_y_start + thickness / 2.0))),
+                );
+                path.assign("stroke-width", thickness);
+                if let Some(color) = edge_color.as_deref() {
+                    path.assign("style", format!("stroke:{color}"));
+                }
+                at(((left_x + right_x) / 2.0, lane_y))
+            } else {
+                path.assign(
+                    "d",
+                    path::Data::new()
+                        .move_to(at((from_x, from_y_start)))
+                        .cubic_curve_to(curve(
+                            at((mid_x, from_y_start)),
+                            at((mid_x, to_y_start)),
+                            at((to_x, to_y_start)),
+                        ))
+                        .line_to(at((to_x, to_y_end)))
+                        .cubic_curve_to(curve(
+                            at((mid_x, to_y_end)),
+                            at((mid_x, from_y_end)),
+                            at((from_x, from_y_end)),
+                        ))
+                        .close(),
+                );
+                if let Some(color) = edge_color.as_deref() {
+                    path.assign("style", format!("fill:{color}"));
+                }
+                at((mid_x, (from_y_start + to_y_end) / 2.0))
+            };
             group.append(path);
 
+            let (label_x, label_y) = label_position;
+
             let mut text = Text::new();
-            text.assign("x", mid_x);
-            text.assign("y", mid_y);
+            text.assign("x", label_x);
+            text.assign("y", label_y);
             let number = style
                 .number_format
                 .as_ref()
                 .map_or(edge.value.to_string(), |f| f(edge.value));
             if let Some(label) = &edge.label {
                 let mut top = Element::new("tspan");
-                top.assign("x", 0);
+                top.assign("x", label_x);
                 top.assign("dy", -font_size);
                 top.append(node::Text::new(label));
                 text.append(top);
                 let mut bottom = Element::new("tspan");
-                bottom.assign("x", 0);
+                bottom.assign("x", label_x);
                 bottom.assign("dy", font_size);
                 bottom.append(node::Text::new(number));
                 text.append(bottom);
@@ -343,10 +1119,120 @@ text.node, .edge > text {{
             svg_edges.push((edge.value, group));
         }
 
+        // Generate imbalance stubs, below the edges already attached to each side of a node
+
+        let mut svg_imbalances = Vec::new();
+
+        let stub_length = layer_width / 4.0;
+
+        if show_imbalance {
+            for (id, node) in self.nodes.iter().enumerate() {
+                let (x, input_y, output_y) = positions[id];
+                let stubs = [
+                    ("input", node.unaccounted_input(), x - stub_length, input_y),
+                    (
+                        "output",
+                        node.unaccounted_output(),
+                        x + node_width,
+                        output_y,
+                    ),
+                ];
+
+                for (class, value, stub_x, stub_y) in stubs {
+                    if value <= IMBALANCE_TOLERANCE {
+                        continue;
+                    }
+                    let thickness = value * min_scale;
+
+                    let mut group = Group::new();
+                    group.assign("class", format!("imbalance {class}"));
+                    group.assign("data-node", id);
+                    group.assign("data-value", value);
+
+                    let mut path = Path::new();
+                    path.assign(
+                        "d",
+                        path::Data::new()
+                            .move_to(at((stub_x, stub_y)))
+                            .line_to(at((stub_x + stub_length, stub_y)))
+                            .line_to(at((stub_x + stub_length, stub_y + thickness)))
+                            .line_to(at((stub_x, stub_y + thickness)))
+                            .close(),
+                    );
+                    path.assign("class", format!("imbalance {class}"));
+                    group.append(path);
+
+                    if label_imbalance {
+                        let number = style
+                            .number_format
+                            .as_ref()
+                            .map_or(value.to_string(), |f| f(value));
+                        // Just beyond the faded end of the stub
+                        let (label_x, label_y) = at((
+                            match class {
+                                "input" => stub_x - font_size / 2.0,
+                                _ => stub_x + stub_length + font_size / 2.0,
+                            },
+                            stub_y + thickness / 2.0,
+                        ));
+                        let mut text = Text::new();
+                        text.assign("x", label_x);
+                        text.assign("y", label_y);
+                        text.assign("class", "imbalance");
+                        text.assign("text-anchor", orientation.anchor(class == "output"));
+                        text.append(node::Text::new(number));
+                        group.append(text);
+                    }
+
+                    svg_imbalances.push(group);
+                }
+            }
+        }
+
+        // Generate legend, listing the ordinal levels from the top-left corner
+
+        let mut legend = Group::new();
+        legend.assign("class", "legend");
+
+        if let Some(scale) = ordinal {
+            legend.assign("data-name", scale.name.as_str());
+
+            let mut title = Text::new();
+            title.assign("x", font_size / 2.0);
+            title.assign("y", font_size);
+            title.assign("class", "legend");
+            title.append(node::Text::new(&scale.name));
+            legend.append(title);
+
+            for (index, level) in scale.levels.iter().enumerate() {
+                let y = font_size * (2.5 + index as f64 * 1.5);
+
+                let mut swatch = Rectangle::new();
+                swatch.assign("x", font_size / 2.0);
+                swatch.assign("y", y - font_size / 2.0);
+                swatch.assign("width", font_size);
+                swatch.assign("height", font_size);
+                swatch.assign("data-level", level.as_str());
+                swatch.assign("style", format!("fill:{}", scale.level_color(index)));
+                legend.append(swatch);
+
+                let mut text = Text::new();
+                text.assign("x", font_size * 2.0);
+                text.assign("y", y);
+                text.assign("class", "legend");
+                text.append(node::Text::new(level));
+                legend.append(text);
+            }
+        }
+
         // Add to SVG
 
         svg_edges.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
 
+        for imbalance in svg_imbalances {
+            document.append(imbalance);
+        }
+
         for node in svg_nodes {
             document.append(node);
         }
@@ -359,6 +1245,10 @@ text.node, .edge > text {{
             document.append(label);
         }
 
+        if ordinal.is_some() {
+            document.append(legend);
+        }
+
         document
     }
 }
@@ -367,6 +1257,8 @@ pub struct SankeyNode {
     value: Option<f64>,
     label: Option<String>,
     color: Option<String>,
+    key: Option<String>,
+    metadata: BTreeMap<String, String>,
     current_input: f64,
     current_output: f64,
 }
@@ -377,6 +1269,8 @@ impl SankeyNode {
             value,
             label,
             color,
+            key: None,
+            metadata: BTreeMap::new(),
             current_input: 0.0,
             current_output: 0.0,
         }
@@ -402,22 +1296,162 @@ impl SankeyNode {
         self.value
             .unwrap_or(f64::max(self.current_input, self.current_output))
     }
+
+    /// Inflow missing from the node's edges. A node without inputs is a source, so nothing is
+    /// missing.
+    pub fn unaccounted_input(&self) -> f64 {
+        match self.current_input > 0.0 {
+            true => f64::max(self.remaining_input(), 0.0),
+            false => 0.0,
+        }
+    }
+
+    /// Outflow that leaves the diagram instead of following an edge. A node without outputs is a
+    /// sink, so nothing is missing.
+    pub fn unaccounted_output(&self) -> f64 {
+        match self.current_output > 0.0 {
+            true => f64::max(self.remaining_output(), 0.0),
+            false => 0.0,
+        }
+    }
 }
 
-#[derive(Copy, Clone, Debug)]
+/// A node whose declared value disagrees with the sum of its edges.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SankeyImbalance {
+    pub node: SankeyNodeID,
+    pub label: Option<String>,
+    pub value: f64,
+    pub input: f64,
+    pub output: f64,
+}
+
+impl Display for SankeyImbalance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "node {} ({}): value {}, edges in {}, edges out {}",
+            self.node.0,
+            self.label.as_deref().unwrap_or("unlabelled"),
+            self.value,
+            self.input,
+            self.output
+        )
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct SankeyNodeID(usize);
 
+impl SankeyNodeID {
+    /// Position of the node in insertion order.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct SankeyEdgeID(usize);
+
+impl SankeyEdgeID {
+    /// Position of the edge in insertion order.
+    pub fn index(self) -> usize {
+        self.0
+    }
+}
+
 pub struct SankeyEdge {
     source: SankeyNodeID,
     target: SankeyNodeID,
     value: f64,
     label: Option<String>,
     color: Option<String>,
+    subjects: Vec<String>,
+    metadata: BTreeMap<String, String>,
+}
+
+// A backward edge or self-loop, routed around the diagram instead of between two layers
+#[derive(Clone, Copy, Debug)]
+struct SankeyLoop {
+    edge: SankeyEdgeID,
+    above: bool,
+    // Number of layers between the source and the target
+    span: usize,
+}
+
+// Control points and end point of a cubic Bézier segment
+fn curve(
+    (x1, y1): (f64, f64),
+    (x2, y2): (f64, f64),
+    (x, y): (f64, f64),
+) -> (f64, f64, f64, f64, f64, f64) {
+    (x1, y1, x2, y2, x, y)
+}
+
+// Reduces text to the letters and digits allowed in SVG ids, with runs of anything else
+// replaced by a single hyphen, so that "(D2, G1)" becomes "D2-G1"
+fn svg_name(text: &str) -> String {
+    text.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+fn data_attribute(name: &str) -> String {
+    format!("data-{}", svg_name(name).to_ascii_lowercase())
+}
+
+// Differences smaller than this are rounding errors rather than imbalance
+const IMBALANCE_TOLERANCE: f64 = 1e-9;
+
+/// Largest scale, in pixels per unit of flow, at which every one of `sankeys` fits.
+/// Drawing them all at this scale makes their ribbon widths comparable.
+pub fn shared_scale<'a, F: Fn(f64) -> String>(
+    sankeys: impl IntoIterator<Item = &'a Sankey>,
+    width: f64,
+    height: f64,
+    style: &SankeyStyle<F>,
+) -> f64 {
+    sankeys
+        .into_iter()
+        .map(|sankey| sankey.fit_scale(width, height, style))
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// An edge weight describing the flow of an identified subject (e.g. a patient) between two nodes.
+pub trait SubjectFlow {
+    fn value(&self) -> f64;
+    fn subject(&self) -> String;
+}
+
+// Adds a node for a graph node, keyed by its Display form so it can be found again by value
+fn convert_node<N: Display>(
+    sankey: &mut Sankey,
+    weight: &N,
+    label: String,
+    metadata: Vec<(String, String)>,
+) -> SankeyNodeID {
+    let id = sankey.node(None, Some(label), None);
+    sankey.set_node_key(id, weight.to_string());
+    for (name, value) in metadata {
+        sankey.set_node_metadata(id, name, value);
+    }
+    id
 }
 
 pub fn convert_to_sankey<N: Clone + Display, E: Clone>(
     graph: Graph<N, E, Directed>,
     node_labeller: &dyn Fn(N) -> String,
+) -> Sankey {
+    convert_to_sankey_with_metadata(graph, node_labeller, &|_| Vec::new())
+}
+
+/// Like [`convert_to_sankey`], but also stores the named values returned by `node_metadata` (e.g.
+/// a node's dose and grade) as node metadata, for layering, colouring and `data-*` attributes.
+pub fn convert_to_sankey_with_metadata<N: Clone + Display, E: Clone>(
+    graph: Graph<N, E, Directed>,
+    node_labeller: &dyn Fn(N) -> String,
+    node_metadata: &dyn Fn(&N) -> Vec<(String, String)>,
 ) -> Sankey {
     let mut sankey = Sankey::new();
 
@@ -427,8 +1461,13 @@ pub fn convert_to_sankey<N: Clone + Display, E: Clone>(
 
     // Convert nodes
     for node in graph.node_indices() {
-        let label = graph.node_weight(node).unwrap().clone(); // Assumes Option<String> as node weight
-        let id = sankey.node(None, Some(node_labeller(label)), None); // Assumes no node value or color specified in petgraph
+        let weight = graph.node_weight(node).unwrap();
+        let id = convert_node(
+            &mut sankey,
+            weight,
+            node_labeller(weight.clone()),
+            node_metadata(weight),
+        ); // Assumes no node value or color specified in petgraph
         node_mapping.insert(node, id);
     }
 
@@ -442,3 +1481,403 @@ pub fn convert_to_sankey<N: Clone + Display, E: Clone>(
 
     sankey
 }
+
+/// Like [`convert_to_sankey`], but merges parallel edges into a single ribbon whose value is the
+/// sum of their weights and which remembers the subjects that took that path.
+pub fn convert_to_subject_sankey<N: Clone + Display, E: SubjectFlow>(
+    graph: Graph<N, E, Directed>,
+    node_labeller: &dyn Fn(N) -> String,
+) -> Sankey {
+    convert_to_subject_sankey_with_metadata(graph, node_labeller, &|_| Vec::new())
+}
+
+/// Like [`convert_to_subject_sankey`], but also stores node metadata, as
+/// [`convert_to_sankey_with_metadata`] does.
+pub fn convert_to_subject_sankey_with_metadata<N: Clone + Display, E: SubjectFlow>(
+    graph: Graph<N, E, Directed>,
+    node_labeller: &dyn Fn(N) -> String,
+    node_metadata: &dyn Fn(&N) -> Vec<(String, String)>,
+) -> Sankey {
+    let mut sankey = Sankey::new();
+
+    let mut node_mapping: std::collections::HashMap<NodeIndex, SankeyNodeID> =
+        std::collections::HashMap::new();
+
+    for node in graph.node_indices() {
+        let weight = graph.node_weight(node).unwrap();
+        let id = convert_node(
+            &mut sankey,
+            weight,
+            node_labeller(weight.clone()),
+            node_metadata(weight),
+        );
+        node_mapping.insert(node, id);
+    }
+
+    // Sum parallel edges, keeping the order in which each (source, target) pair was first seen
+    let mut ribbons: Vec<((NodeIndex, NodeIndex), f64, Vec<String>)> = Vec::new();
+
+    for edge in graph.edge_indices() {
+        let endpoints = graph.edge_endpoints(edge).unwrap();
+        let weight = graph.edge_weight(edge).unwrap();
+
+        match ribbons.iter_mut().find(|(key, ..)| *key == endpoints) {
+            Some((_, value, subjects)) => {
+                *value += weight.value();
+                subjects.push(weight.subject());
+            }
+            None => ribbons.push((endpoints, weight.value(), vec![weight.subject()])),
+        }
+    }
+
+    for ((source, target), value, subjects) in ribbons {
+        let source_id = *node_mapping.get(&source).unwrap();
+        let target_id = *node_mapping.get(&target).unwrap();
+        let edge = sankey.edge(source_id, target_id, value, None, None);
+        for subject in subjects {
+            sankey.add_subject(edge, subject);
+        }
+    }
+
+    sankey
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cohort(size: f64) -> Sankey {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("a".to_string()), None);
+        let b = sankey.node(None, Some("b".to_string()), None);
+        sankey.edge(a, b, size, None, None);
+        sankey
+    }
+
+    #[test]
+    fn scale_can_be_fixed_or_shared() {
+        let (small, large) = (cohort(10.0), cohort(40.0));
+        let style = SankeyStyle::<fn(f64) -> String> {
+            border: Some(0.0),
+            ..SankeyStyle::default()
+        };
+
+        assert_eq!(small.scale(300.0, 200.0, &style), 20.0);
+        assert_eq!(shared_scale([&small, &large], 300.0, 200.0, &style), 5.0);
+
+        let style = SankeyStyle {
+            scale: Some(5.0),
+            ..style
+        };
+        assert_eq!(small.scale(300.0, 200.0, &style), 5.0);
+
+        let svg = small.draw(300.0, 200.0, style).to_string();
+        assert!(svg.contains("data-scale=\"5\""));
+        assert!(svg.contains("height=\"50\""));
+    }
+
+    #[test]
+    fn imbalance_is_drawn_and_reported() {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("a".to_string()), None);
+        let b = sankey.node(Some(10.0), Some("b".to_string()), None);
+        let c = sankey.node(None, Some("c".to_string()), None);
+        sankey.edge(a, b, 6.0, None, None);
+        sankey.edge(b, c, 8.0, None, None);
+
+        // 4 of b's inflow comes from outside the diagram, and 2 of its outflow leaves it
+        assert_eq!(sankey.unaccounted_input(b), 4.0);
+        assert_eq!(sankey.unaccounted_output(b), 2.0);
+        assert_eq!(sankey.nodes[a.0].unaccounted_input(), 0.0);
+        assert_eq!(sankey.nodes[c.0].unaccounted_output(), 0.0);
+
+        let report = sankey.validate();
+        assert_eq!(report.len(), 1);
+        assert_eq!(report[0].node, b);
+        assert_eq!(
+            report[0].to_string(),
+            "node 1 (b): value 10, edges in 6, edges out 8"
+        );
+
+        let style = SankeyStyle::<fn(f64) -> String> {
+            show_imbalance: Some(true),
+            label_imbalance: Some(true),
+            ..SankeyStyle::default()
+        };
+        let svg = sankey.draw(300.0, 200.0, style).to_string();
+        assert!(svg.contains("class=\"imbalance input\""));
+        assert!(svg.contains("class=\"imbalance output\""));
+        assert_eq!(svg.matches("<text class=\"imbalance\"").count(), 2);
+
+        // Stubs are opt-in
+        let svg = sankey
+            .draw(300.0, 200.0, SankeyStyle::<fn(f64) -> String>::default())
+            .to_string();
+        assert!(!svg.contains("class=\"imbalance"));
+    }
+
+    // (x, y, width, height) of every node rectangle, in document order
+    fn node_rects(svg: &str) -> Vec<[f64; 4]> {
+        svg.split("<rect")
+            .skip(1)
+            .filter(|rect| rect.contains("class=\"node "))
+            .map(|rect| {
+                let attribute = |name: &str| -> f64 {
+                    let start = rect.find(&format!(" {name}=\"")).unwrap() + name.len() + 3;
+                    let end = start + rect[start..].find('"').unwrap();
+                    rect[start..end].parse().unwrap()
+                };
+                [
+                    attribute("x"),
+                    attribute("y"),
+                    attribute("width"),
+                    attribute("height"),
+                ]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn orientations_transpose_node_rects() {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("a".to_string()), None);
+        let b = sankey.node(None, Some("b".to_string()), None);
+        let c = sankey.node(None, Some("c".to_string()), None);
+        sankey.edge(a, b, 3.0, None, None);
+        sankey.edge(a, c, 1.0, None, None);
+
+        let draw = |width, height, orientation| {
+            let style = SankeyStyle::<fn(f64) -> String> {
+                orientation: Some(orientation),
+                ..SankeyStyle::default()
+            };
+            sankey.draw(width, height, style).to_string()
+        };
+
+        // Text is sized by the breadth of the diagram, so it doesn't change when rotated
+        let svg = draw(300.0, 400.0, SankeyOrientation::TopToBottom);
+        assert!(svg.contains("font-size: 6px"));
+        assert!(draw(400.0, 300.0, SankeyOrientation::LeftToRight).contains("font-size: 6px"));
+
+        let left_to_right = node_rects(&draw(400.0, 300.0, SankeyOrientation::LeftToRight));
+        let right_to_left = node_rects(&draw(400.0, 300.0, SankeyOrientation::RightToLeft));
+        let top_to_bottom = node_rects(&svg);
+
+        assert_eq!(left_to_right.len(), 3);
+        for ((&[x, y, w, h], rtl), ttb) in
+            left_to_right.iter().zip(&right_to_left).zip(&top_to_bottom)
+        {
+            assert!((rtl[0] - (400.0 - x - w)).abs() < 1e-9);
+            assert_eq!(rtl[1..], [y, w, h]);
+            assert_eq!(*ttb, [y, x, h, w]);
+        }
+    }
+
+    #[test]
+    fn loops_are_routed_around_the_diagram() {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, Some("a".to_string()), None);
+        let b = sankey.node(None, Some("b".to_string()), None);
+        let c = sankey.node(None, Some("c".to_string()), None);
+        sankey.edge(a, b, 4.0, None, None);
+        sankey.edge(b, c, 4.0, None, None);
+        sankey.edge(c, a, 2.0, None, None);
+        sankey.edge(b, b, 1.0, None, None);
+
+        // The back edge and the self-loop don't stop the nodes from being layered
+        assert_eq!(sankey.circular_edges(), [false, false, true, true]);
+        let layers = sankey.layers(None, None);
+        assert_eq!(layers, [[a], [b], [c]]);
+
+        // The self-loop spans no layers, so it takes the inner lane
+        let loops = sankey.loops(&layers);
+        assert_eq!(
+            loops.iter().map(|l| (l.edge.0, l.span)).collect::<Vec<_>>(),
+            [(3, 0), (2, 2)]
+        );
+
+        let style = SankeyStyle::<fn(f64) -> String> {
+            border: Some(0.0),
+            node_separation: Some(10.0),
+            ..SankeyStyle::default()
+        };
+        // Node b (a flow of 5, including its self-loop) shares the height with two lanes of 2
+        // and 1, each 5 away from the diagram
+        assert_eq!(sankey.scale(400.0, 100.0, &style), 90.0 / 8.0);
+
+        let svg = sankey.draw(400.0, 100.0, style).to_string();
+        assert_eq!(svg.matches("class=\"edge circular ").count(), 2);
+        for [_, y, _, height] in node_rects(&svg) {
+            assert!(y >= 0.0 && y + height <= 100.0);
+        }
+    }
+
+    #[test]
+    fn metadata_is_drawn_and_nodes_found_by_key() {
+        use crate::models::{CytokineReleaseSyndromeGrade, DosageEvent, Dose, EdgeDatum};
+
+        let event = |dose, grade| DosageEvent { dose, grade };
+        let mut graph = Graph::<DosageEvent, EdgeDatum, Directed>::new();
+        let d1 = graph.add_node(event(Dose::D1, CytokineReleaseSyndromeGrade::G0));
+        let d2 = graph.add_node(event(Dose::D2, CytokineReleaseSyndromeGrade::G1));
+        graph.add_edge(
+            d1,
+            d2,
+            EdgeDatum {
+                weight: 1,
+                subject_id: 10021001,
+            },
+        );
+
+        let mut sankey = convert_to_subject_sankey_with_metadata(
+            graph,
+            &|event| event.to_string(),
+            &DosageEvent::metadata,
+        );
+        let node = sankey
+            .node_by_key(&event(Dose::D2, CytokineReleaseSyndromeGrade::G1).to_string())
+            .unwrap();
+        assert_eq!(node.index(), 1);
+        assert_eq!(sankey.node_metadata(node)["grade"], "G1");
+
+        let edge = sankey.edge_ids().next().unwrap();
+        sankey.set_edge_metadata(edge, "Arm".to_string(), "A".to_string());
+
+        // Any Display node converts, without metadata
+        let mut plain = Graph::<u32, (), Directed>::new();
+        let one = plain.add_node(1);
+        let two = plain.add_node(2);
+        plain.add_edge(one, two, ());
+        let plain = convert_to_sankey(plain, &|n| n.to_string());
+        assert!(plain
+            .node_metadata(plain.node_by_key("2").unwrap())
+            .is_empty());
+
+        // Keys move rather than being shared
+        let other = sankey.node(None, None, None);
+        sankey.set_node_key(other, "(D1, G0)".to_string());
+        assert_eq!(sankey.node_by_key("(D1, G0)"), Some(other));
+        assert_eq!(sankey.node_key(SankeyNodeID(0)), None);
+
+        let svg = sankey
+            .draw(400.0, 300.0, SankeyStyle::<fn(f64) -> String>::default())
+            .to_string();
+        assert!(svg.contains("id=\"node-D2-G1\""));
+        assert!(svg.contains("data-dose=\"D2\""));
+        assert!(svg.contains("data-arm=\"A\""));
+    }
+
+    #[test]
+    fn ids_and_classes_are_stable() {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, None, None);
+        let b = sankey.node(None, None, None);
+        sankey.set_node_key(b, "(D2, G1)".to_string());
+        sankey.set_node_metadata(b, "Grade".to_string(), "G1".to_string());
+        let edge = sankey.edge(a, b, 1.0, None, None);
+        sankey.add_subject(edge, "10021001".to_string());
+        // Parallel edges without subjects would share an id, so the second is numbered
+        sankey.edge(a, b, 1.0, None, None);
+        sankey.edge(a, b, 1.0, None, None);
+
+        let draw = || {
+            sankey
+                .draw(400.0, 300.0, SankeyStyle::<fn(f64) -> String>::default())
+                .to_string()
+        };
+        let svg = draw();
+        assert_eq!(svg, draw());
+
+        assert!(svg.contains("id=\"node-0\""));
+        assert!(svg.contains("class=\"node layer-1 grade-G1\""));
+        assert!(svg.contains("id=\"edge-0--D2-G1-subj-10021001\""));
+        assert!(svg.contains("class=\"edge layer-0 from-0 to-D2-G1 subject-10021001\""));
+        assert!(svg.contains("id=\"edge-0--D2-G1\""));
+        assert!(svg.contains("id=\"edge-0--D2-G1-2\""));
+    }
+
+    #[test]
+    fn ordinal_levels_colour_nodes_and_ribbons() {
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, None, None);
+        let b = sankey.node(None, None, None);
+        let c = sankey.node(None, None, Some("#123456".to_string()));
+        for (node, grade) in [(a, "G0"), (b, "G2"), (c, "G1")] {
+            sankey.set_node_metadata(node, "grade".to_string(), grade.to_string());
+        }
+        sankey.edge(a, b, 1.0, None, None);
+        sankey.edge(a, c, 1.0, None, None);
+
+        let scale = OrdinalScale::new("grade", ["G0", "G1", "G2"].map(String::from).to_vec());
+        assert_eq!(scale.level_color(1).to_string(), "#FC4E2A");
+        // Unlike a green to red ramp, neighbouring levels stay apart with colour-vision
+        // deficiencies, even when there are only two of them
+        assert!(scale.warnings().is_empty());
+        let binary = OrdinalScale::new("event", ["no", "yes"].map(String::from).to_vec());
+        assert!(binary.warnings().is_empty());
+        let traffic_light = OrdinalScale {
+            colors: ["#1A9850", "#FDAE61", "#D73027"]
+                .map(|hex| Rgb::from_hex(hex).unwrap())
+                .to_vec(),
+            ..binary
+        };
+        assert!(!traffic_light.warnings().is_empty());
+
+        let draw = |color_ribbons| {
+            let style = SankeyStyle::<fn(f64) -> String> {
+                ordinal: Some(OrdinalScale {
+                    color_ribbons,
+                    ..scale.clone()
+                }),
+                ..SankeyStyle::default()
+            };
+            sankey.draw(400.0, 300.0, style).to_string()
+        };
+
+        // Nodes: yellow, dark red, and the node's own colour; legend: one swatch per grade
+        let svg = draw(false);
+        assert_eq!(svg.matches("fill:#FED976").count(), 2);
+        assert_eq!(svg.matches("fill:#800026").count(), 2);
+        assert_eq!(svg.matches("fill:#123456").count(), 1);
+        assert_eq!(svg.matches("data-level=").count(), 3);
+
+        // Ribbons take their target's colour
+        let svg = draw(true);
+        assert_eq!(svg.matches("fill:#800026").count(), 3);
+        assert_eq!(svg.matches("fill:#FC4E2A").count(), 2);
+    }
+
+    #[test]
+    fn layers_follow_metadata_and_are_ordered() {
+        let mut sankey = Sankey::new();
+        let [a, b, c, d, e] = ["1", "1", "2", "2", "3"].map(|step| {
+            let node = sankey.node(None, None, None);
+            sankey.set_node_metadata(node, "step".to_string(), step.to_string());
+            node
+        });
+        sankey.edge(a, c, 1.0, None, None);
+        sankey.edge(a, d, 5.0, None, None);
+        sankey.edge(b, c, 1.0, None, None);
+        sankey.edge(b, d, 1.0, None, None);
+
+        // The unreached node e has no inputs, so it would otherwise join the first layer
+        assert_eq!(sankey.layers(None, None), [vec![a, b, e], vec![c, d]]);
+
+        let step = SankeyLayering::Metadata("step".to_string());
+        assert_eq!(
+            sankey.layers(Some(&step), None),
+            [vec![a, b], vec![c, d], vec![e]]
+        );
+
+        // Most of d's flow comes from the top node, so its mean source is higher than c's, but
+        // the median source of both is the top node, so they keep their order
+        assert_eq!(
+            sankey.layers(Some(&step), Some(LayerOrderingMethod::Barycenter)),
+            [vec![a, b], vec![d, c], vec![e]]
+        );
+        assert_eq!(
+            sankey.layers(Some(&step), Some(LayerOrderingMethod::Median)),
+            [vec![a, b], vec![c, d], vec![e]]
+        );
+    }
+}
@@ -56,3 +56,35 @@ impl Default for SettingsStyle {
         }
     }
 }
+
+impl From<&SettingsInteraction> for egui_graphs::SettingsInteraction {
+    fn from(settings: &SettingsInteraction) -> Self {
+        egui_graphs::SettingsInteraction::new()
+            .with_dragging_enabled(settings.dragging_enabled)
+            .with_clicking_enabled(settings.clicking_enabled)
+            .with_folding_enabled(settings.folding_enabled)
+            .with_selection_enabled(settings.selection_enabled)
+            .with_selection_multi_enabled(settings.selection_multi_enabled)
+            .with_selection_depth(settings.selection_depth)
+            .with_folding_depth(settings.folding_depth)
+    }
+}
+
+impl From<&SettingsNavigation> for egui_graphs::SettingsNavigation {
+    fn from(settings: &SettingsNavigation) -> Self {
+        egui_graphs::SettingsNavigation::new()
+            .with_fit_to_screen_enabled(settings.fit_to_screen_enabled)
+            .with_zoom_and_pan_enabled(settings.zoom_and_pan_enabled)
+            .with_screen_padding(settings.screen_padding)
+            .with_zoom_speed(settings.zoom_speed)
+    }
+}
+
+impl From<&SettingsStyle> for egui_graphs::SettingsStyle {
+    fn from(settings: &SettingsStyle) -> Self {
+        egui_graphs::SettingsStyle::new()
+            .with_edge_radius_weight(settings.edge_radius_weight)
+            .with_folded_radius_weight(settings.folded_node_radius_weight)
+            .with_labels_always(settings.labels_always)
+    }
+}
@@ -0,0 +1,587 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use svg::node::element::SVG;
+
+use crate::{
+    alluvial::AlluvialStyle,
+    crs_alluvial_from_records, crs_graph_from_records, errors,
+    export::{
+        dot::to_dot,
+        html::save_html,
+        image::{save_pdf, save_png, ImageExportOptions},
+        web::{D3SankeyGraph, PlotlySankeyTrace},
+    },
+    heatmap::{draw_small_multiples, transition_matrices, HeatmapStyle},
+    models::{
+        crs_dose::{read_dose_records, AeDoseCsvRecord, DoseColumns},
+        AltGraph, CytokineReleaseSyndromeGrade, DosageEvent, EdgeDatum,
+    },
+    sankey::LayerOrderingMethod,
+    sankey_graph::{
+        convert_to_subject_sankey_with_metadata, OrdinalScale, SankeyLayering, SankeyOrientation,
+        SankeyStyle,
+    },
+    swimmer::{LaneOrder, SwimmerPlot, SwimmerStyle},
+    theme::Theme,
+};
+
+#[derive(thiserror::Error, Debug)]
+pub enum SpecError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error("JSON parsing error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("TOML parsing error: {0}")]
+    Toml(#[from] toml_edit::de::Error),
+    #[error("Unknown theme `{0}`")]
+    UnknownTheme(String),
+    #[error("Cannot tell the format of {0} from its extension")]
+    UnknownFormat(PathBuf),
+}
+
+/// A chart described as data: where its records come from, which of them to keep, how to lay
+/// out and style it, and where to write it. Read from JSON, or from TOML if the file ends in
+/// `.toml`, and drawn with [`render_spec`]. Relative paths are taken from the spec's directory.
+///
+/// ```toml
+/// chart = "sankey"
+///
+/// [data]
+/// path = "dose.csv"
+///
+/// [[filters]]
+/// field = "state"
+/// min = 1
+///
+/// [layout]
+/// layering = "step"
+/// ordering = "barycenter"
+///
+/// [style]
+/// theme = "colour-blind"
+///
+/// [[outputs]]
+/// path = "dose.svg"
+/// ```
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChartSpec {
+    pub data: DataSpec,
+    #[serde(default)]
+    pub chart: ChartKind,
+    /// Applied in turn, each to the records kept by the previous ones.
+    #[serde(default)]
+    pub filters: Vec<Filter>,
+    #[serde(default)]
+    pub layout: LayoutSpec,
+    #[serde(default)]
+    pub style: StyleSpec,
+    #[serde(default)]
+    pub labels: LabelSpec,
+    pub outputs: Vec<OutputSpec>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DataSpec {
+    /// Dose CSV, with one row per subject and step.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub fields: FieldSpec,
+}
+
+/// CSV columns holding the subject id, the step (dose number) and the state (grade).
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldSpec {
+    pub subject: String,
+    pub step: String,
+    pub state: String,
+}
+
+impl Default for FieldSpec {
+    fn default() -> Self {
+        let columns = DoseColumns::default();
+        FieldSpec {
+            subject: columns.subject,
+            step: columns.dose,
+            state: columns.grade,
+        }
+    }
+}
+
+impl From<&FieldSpec> for DoseColumns {
+    fn from(fields: &FieldSpec) -> Self {
+        DoseColumns {
+            subject: fields.subject.clone(),
+            dose: fields.step.clone(),
+            grade: fields.state.clone(),
+            ..DoseColumns::default()
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ChartKind {
+    #[default]
+    Sankey,
+    Alluvial,
+    Swimmer,
+    // Transition matrices between consecutive steps.
+    Heatmap,
+}
+
+/// Narrows the records down. Subject and state filters keep or drop whole subjects, so that
+/// every kept trajectory stays complete, while step filters cut every trajectory to a range of
+/// steps.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "field", rename_all = "kebab-case", deny_unknown_fields)]
+pub enum Filter {
+    // Keeps the listed subjects.
+    Subject {
+        #[serde(rename = "in")]
+        values: Vec<i32>,
+    },
+    // Keeps the steps from `min` to `max`, inclusive.
+    Step {
+        min: Option<i32>,
+        max: Option<i32>,
+    },
+    // Keeps the subjects that were in a state from `min` to `max` at some step.
+    State {
+        min: Option<i32>,
+        max: Option<i32>,
+    },
+}
+
+impl Filter {
+    pub fn apply(&self, records: Vec<AeDoseCsvRecord>) -> Vec<AeDoseCsvRecord> {
+        let within = |value: i32, min: Option<i32>, max: Option<i32>| {
+            min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max)
+        };
+
+        match self {
+            Filter::Subject { values } => records
+                .into_iter()
+                .filter(|record| values.contains(&record.subject_id))
+                .collect(),
+            Filter::Step { min, max } => records
+                .into_iter()
+                .filter(|record| within(record.dose_number, *min, *max))
+                .collect(),
+            Filter::State { min, max } => {
+                let subjects: Vec<i32> = records
+                    .iter()
+                    .filter(|record| within(record.cytokine_release_syndrome_grade_id, *min, *max))
+                    .map(|record| record.subject_id)
+                    .collect();
+                records
+                    .into_iter()
+                    .filter(|record| subjects.contains(&record.subject_id))
+                    .collect()
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Layering {
+    // Each node one layer after its furthest predecessor. States that no subject reached fall
+    // back to the first layer.
+    LongestPath,
+    // One layer per step, keeping states that no subject reached in line with their step.
+    #[default]
+    Step,
+}
+
+impl From<Layering> for SankeyLayering {
+    fn from(layering: Layering) -> Self {
+        match layering {
+            Layering::LongestPath => SankeyLayering::LongestPath,
+            // The step is the `dose` entry of the nodes' metadata
+            Layering::Step => SankeyLayering::Metadata("dose".to_string()),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LayoutSpec {
+    pub width: f64,
+    pub height: f64,
+    pub orientation: Option<SankeyOrientation>,
+    pub layering: Layering,
+    /// Order of the nodes within each layer. Insertion order if unset.
+    pub ordering: Option<LayerOrderingMethod>,
+    pub node_width: Option<f64>,
+    pub node_separation: Option<f64>,
+    pub border: Option<f64>,
+}
+
+impl Default for LayoutSpec {
+    fn default() -> Self {
+        LayoutSpec {
+            width: 1000.0,
+            height: 600.0,
+            orientation: None,
+            layering: Layering::default(),
+            ordering: None,
+            node_width: None,
+            node_separation: None,
+            border: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleSpec {
+    /// Built-in theme: "light", "dark", "print" or "colour-blind".
+    pub theme: Option<String>,
+    /// Theme JSON file, used if `theme` is unset.
+    pub theme_file: Option<PathBuf>,
+    pub font_family: Option<String>,
+    pub font_size: Option<f64>,
+    pub font_color: Option<String>,
+    /// Colours nodes by state, with a legend.
+    pub color_states: bool,
+    /// Also colours ribbons by the state they lead to.
+    pub color_ribbons: bool,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LabelSpec {
+    /// Title of HTML pages. The spec's file name if unset.
+    pub title: Option<String>,
+    /// Decimal places of the values shown on nodes and ribbons.
+    pub decimals: Option<usize>,
+    /// Draws faded stubs for flow entering or leaving a node outside of any edge.
+    pub show_imbalance: Option<bool>,
+    pub label_imbalance: Option<bool>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OutputSpec {
+    pub path: PathBuf,
+    /// Taken from the extension of `path` if unset. A `.json` file gets the graph in the format
+    /// read by the explorer.
+    pub format: Option<OutputFormat>,
+    /// Resolution of PNG and PDF output.
+    pub dpi: Option<f32>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    Svg,
+    Png,
+    Pdf,
+    Html,
+    // Graphviz, for the transition graph rather than the drawn chart.
+    Dot,
+    Json,
+    Plotly,
+    D3,
+}
+
+impl OutputFormat {
+    pub fn from_path(path: &Path) -> Option<OutputFormat> {
+        match path.extension()?.to_str()?.to_lowercase().as_str() {
+            "svg" => Some(OutputFormat::Svg),
+            "png" => Some(OutputFormat::Png),
+            "pdf" => Some(OutputFormat::Pdf),
+            "html" | "htm" => Some(OutputFormat::Html),
+            "dot" | "gv" => Some(OutputFormat::Dot),
+            "json" => Some(OutputFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+impl ChartSpec {
+    pub fn from_json(json: &str) -> Result<ChartSpec, SpecError> {
+        Ok(serde_json::from_str(json)?)
+    }
+
+    pub fn from_toml(toml: &str) -> Result<ChartSpec, SpecError> {
+        Ok(toml_edit::de::from_str(toml)?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<ChartSpec, SpecError> {
+        let contents = std::fs::read_to_string(&path)?;
+
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("toml") => ChartSpec::from_toml(&contents),
+            _ => ChartSpec::from_json(&contents),
+        }
+    }
+
+    /// Reads the data source and applies the filters.
+    pub fn records(&self, base: &Path) -> errors::Result<Vec<AeDoseCsvRecord>> {
+        let records = read_dose_records(base.join(&self.data.path), &(&self.data.fields).into())?;
+
+        Ok(self
+            .filters
+            .iter()
+            .fold(records, |records, filter| filter.apply(records)))
+    }
+
+    pub fn sankey_style(
+        &self,
+        base: &Path,
+    ) -> Result<SankeyStyle<impl Fn(f64) -> String + Clone>, SpecError> {
+        let theme = match (&self.style.theme, &self.style.theme_file) {
+            (Some(name), _) => {
+                Some(Theme::named(name).ok_or_else(|| SpecError::UnknownTheme(name.clone()))?)
+            }
+            (None, Some(path)) => Some(Theme::from_json_file(base.join(path)).map_err(
+                |error| match error {
+                    crate::file_op::JsonReadError::Io(error) => SpecError::Io(error),
+                    crate::file_op::JsonReadError::Parsing(error) => SpecError::Json(error),
+                },
+            )?),
+            (None, None) => None,
+        };
+        let ordinal = match self.style.color_states {
+            true => Some(OrdinalScale {
+                color_ribbons: self.style.color_ribbons,
+                ..CytokineReleaseSyndromeGrade::ordinal_scale()
+            }),
+            false => None,
+        };
+
+        Ok(SankeyStyle {
+            number_format: self
+                .labels
+                .decimals
+                .map(|decimals| move |value: f64| format!("{value:.decimals$}")),
+            node_separation: self.layout.node_separation,
+            node_width: self.layout.node_width,
+            font_family: self.style.font_family.clone(),
+            font_size: self.style.font_size,
+            font_color: self.style.font_color.clone(),
+            border: self.layout.border,
+            show_imbalance: self.labels.show_imbalance,
+            label_imbalance: self.labels.label_imbalance,
+            orientation: self.layout.orientation,
+            layering: Some(self.layout.layering.into()),
+            ordering: self.layout.ordering,
+            theme,
+            ordinal,
+            ..SankeyStyle::default()
+        })
+    }
+}
+
+/// Reads the chart spec at `path`, builds and draws its chart, and writes every output. Returns
+/// the paths written.
+pub fn render_spec<P: AsRef<Path>>(path: P) -> errors::Result<Vec<PathBuf>> {
+    let path = path.as_ref();
+    let spec = ChartSpec::from_file(path)?;
+    let base = path.parent().unwrap_or(Path::new(""));
+
+    let records = spec.records(base)?;
+    let style = spec.sankey_style(base)?;
+    let graph = crs_graph_from_records(&records)?;
+    let labeller = |event: DosageEvent| event.to_string();
+    let weight = |edge: &EdgeDatum| edge.weight as f64;
+    let (width, height) = (spec.layout.width, spec.layout.height);
+
+    let svg: SVG = match spec.chart {
+        ChartKind::Sankey => convert_to_subject_sankey_with_metadata(
+            graph.clone(),
+            &labeller,
+            &DosageEvent::metadata,
+        )
+        .draw(width, height, style),
+        ChartKind::Alluvial => crs_alluvial_from_records(&records)?.draw(
+            width,
+            height,
+            AlluvialStyle {
+                number_format: style.number_format,
+                font_family: style.font_family,
+                font_size: style.font_size,
+                font_color: style.font_color,
+                border: style.border,
+                ..AlluvialStyle::default()
+            },
+        ),
+        ChartKind::Swimmer => SwimmerPlot::from_records(&records)?.draw(
+            width,
+            height,
+            SwimmerStyle {
+                lane_order: Some(LaneOrder::MaxGrade),
+                font_family: style.font_family,
+                font_size: style.font_size,
+                font_color: style.font_color,
+                border: style.border,
+                ..SwimmerStyle::default()
+            },
+        ),
+        ChartKind::Heatmap => {
+            let matrices = transition_matrices(
+                &graph,
+                &|event: &DosageEvent| event.dose,
+                &labeller,
+                &weight,
+            );
+            draw_small_multiples(
+                &matrices,
+                matrices.len(),
+                width,
+                height,
+                HeatmapStyle::from(style),
+            )
+        }
+    };
+
+    let title = spec.labels.title.clone().unwrap_or_else(|| {
+        path.file_stem()
+            .map_or(String::new(), |stem| stem.to_string_lossy().into_owned())
+    });
+
+    let mut written = Vec::new();
+
+    for output in &spec.outputs {
+        let target = base.join(&output.path);
+        let format = output
+            .format
+            .or_else(|| OutputFormat::from_path(&output.path))
+            .ok_or_else(|| SpecError::UnknownFormat(output.path.clone()))?;
+        let options = ImageExportOptions {
+            dpi: output.dpi,
+            ..ImageExportOptions::default()
+        };
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        match format {
+            OutputFormat::Svg => svg::save(&target, &svg)?,
+            OutputFormat::Png => save_png(&target, &svg, &options)?,
+            OutputFormat::Pdf => save_pdf(&target, &svg, &options)?,
+            OutputFormat::Html => save_html(&target, &svg, &title)?,
+            OutputFormat::Dot => {
+                std::fs::write(&target, to_dot(&graph, &labeller, &|_| None, &weight))?
+            }
+            OutputFormat::Json => std::fs::write(
+                &target,
+                serde_json::to_string_pretty(&AltGraph::from_graph(&graph))?,
+            )?,
+            OutputFormat::Plotly => std::fs::write(
+                &target,
+                serde_json::to_string_pretty(&PlotlySankeyTrace::from(
+                    &convert_to_subject_sankey_with_metadata(
+                        graph.clone(),
+                        &labeller,
+                        &DosageEvent::metadata,
+                    ),
+                ))?,
+            )?,
+            OutputFormat::D3 => std::fs::write(
+                &target,
+                serde_json::to_string_pretty(&D3SankeyGraph::from(
+                    &convert_to_subject_sankey_with_metadata(
+                        graph.clone(),
+                        &labeller,
+                        &DosageEvent::metadata,
+                    ),
+                ))?,
+            )?,
+        }
+
+        written.push(target);
+    }
+
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::{node_x, TempDir};
+
+    #[test]
+    fn toml_spec_renders_every_output() {
+        let toml = r#"
+[data]
+path = "dose.csv"
+fields = { subject = "ID", step = "Dose", state = "Grade" }
+
+[[filters]]
+field = "state"
+min = 1
+
+[[filters]]
+field = "step"
+max = 2
+
+[layout]
+ordering = "barycenter"
+
+[style]
+theme = "colour-blind"
+
+[[outputs]]
+path = "out/dose.svg"
+
+[[outputs]]
+path = "out/dose.dot"
+"#;
+
+        let spec = ChartSpec::from_toml(toml).unwrap();
+        assert_eq!(spec.chart, ChartKind::Sankey);
+        assert_eq!(spec.layout.ordering, Some(LayerOrderingMethod::Barycenter));
+        assert_eq!(spec.layout.layering, Layering::Step);
+
+        // The data and the outputs are found relative to the spec file
+        let temp = TempDir::new();
+        temp.write(
+            "dose.csv",
+            "ID,Dose,Grade\n1,1,0\n1,2,1\n1,3,0\n2,1,0\n2,2,0\n2,3,0\n3,1,2\n3,2,0\n3,3,0\n",
+        );
+        let spec_path = temp.write("spec.toml", toml);
+        let directory = temp.path();
+
+        // Subject 2 never left grade 0, and the third dose is cut off
+        let records = spec.records(directory).unwrap();
+        assert_eq!(records.len(), 4);
+        assert!(records.iter().all(|record| record.subject_id != 2));
+
+        let written = render_spec(&spec_path).unwrap();
+        assert_eq!(
+            written,
+            [
+                directory.join("out/dose.svg"),
+                directory.join("out/dose.dot")
+            ]
+        );
+        let svg = std::fs::read_to_string(&written[0]).unwrap();
+        assert!(svg.contains("data-key=\"(D1, G2)\""));
+        // Nobody is at grade 2 after the first dose, but that node still sits with the second doses
+        assert_eq!(node_x(&svg, "node-D2-G2"), node_x(&svg, "node-D2-G1"));
+        assert!(node_x(&svg, "node-D2-G2") > node_x(&svg, "node-D1-G2"));
+        assert!(std::fs::read_to_string(&written[1])
+            .unwrap()
+            .starts_with("digraph"));
+
+        // The same spec as JSON, with an unknown theme
+        let mut json = serde_json::to_value(&spec).unwrap();
+        json["style"]["theme"] = "sepia".into();
+        let spec = ChartSpec::from_json(&json.to_string()).unwrap();
+        assert!(matches!(
+            spec.sankey_style(directory),
+            Err(SpecError::UnknownTheme(name)) if name == "sepia"
+        ));
+    }
+}
@@ -0,0 +1,348 @@
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+use strum::IntoEnumIterator;
+use svg::{
+    node::{
+        self,
+        element::{Circle, Group, Line, Style, Text, SVG},
+    },
+    Node,
+};
+
+use crate::models::{
+    crs_dose::AeDoseCsvRecord, CytokineReleaseSyndromeGrade, EnumIntConversionError,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LaneOrder {
+    // Highest grade first, so that the subjects with the worst reactions are at the top.
+    MaxGrade,
+    // Most doses first.
+    DoseCount,
+    SubjectId,
+}
+
+#[derive(Default)]
+pub struct SwimmerStyle {
+    /// Lanes are drawn in their current order (see [`SwimmerPlot::sort_lanes`]) if unset.
+    pub lane_order: Option<LaneOrder>,
+    pub marker_radius: Option<f64>,
+    /// One colour per grade, in grade order. If unset or empty, the grades take the colours of
+    /// [`CytokineReleaseSyndromeGrade::ordinal_scale`], as on the Sankey.
+    pub grade_colors: Option<Vec<String>>,
+    pub font_family: Option<String>,
+    pub font_size: Option<f64>,
+    pub font_color: Option<String>,
+    pub border: Option<f64>,
+}
+
+pub struct SwimmerLane {
+    subject_id: i32,
+    // (dose number, grade), in dose order
+    doses: Vec<(i32, CytokineReleaseSyndromeGrade)>,
+}
+
+impl SwimmerLane {
+    pub fn max_grade(&self) -> Option<CytokineReleaseSyndromeGrade> {
+        self.doses.iter().map(|&(_, grade)| grade).max()
+    }
+
+    fn compare(&self, other: &SwimmerLane, order: LaneOrder) -> Ordering {
+        let key = match order {
+            LaneOrder::MaxGrade => other.max_grade().cmp(&self.max_grade()),
+            LaneOrder::DoseCount => other.doses.len().cmp(&self.doses.len()),
+            LaneOrder::SubjectId => Ordering::Equal,
+        };
+        key.then(self.subject_id.cmp(&other.subject_id))
+    }
+}
+
+fn default_grade_colors() -> Vec<String> {
+    CytokineReleaseSyndromeGrade::ordinal_scale()
+        .level_colors()
+        .iter()
+        .map(ToString::to_string)
+        .collect()
+}
+
+/// A swimmer plot: one horizontal lane per subject, with a marker per dose coloured by grade.
+pub struct SwimmerPlot {
+    lanes: Vec<SwimmerLane>,
+}
+
+impl SwimmerPlot {
+    pub fn from_records(
+        records: &[AeDoseCsvRecord],
+    ) -> Result<SwimmerPlot, EnumIntConversionError> {
+        let mut subjects = BTreeMap::<i32, Vec<(i32, CytokineReleaseSyndromeGrade)>>::new();
+
+        for record in records {
+            let grade: CytokineReleaseSyndromeGrade =
+                record.cytokine_release_syndrome_grade_id.try_into()?;
+            subjects
+                .entry(record.subject_id)
+                .or_default()
+                .push((record.dose_number, grade));
+        }
+
+        let lanes = subjects
+            .into_iter()
+            .map(|(subject_id, mut doses)| {
+                doses.sort();
+                SwimmerLane { subject_id, doses }
+            })
+            .collect();
+
+        Ok(SwimmerPlot { lanes })
+    }
+
+    /// Sorts the lanes from top to bottom. Ties are broken by subject id.
+    pub fn sort_lanes(&mut self, order: LaneOrder) {
+        self.lanes.sort_by(|a, b| a.compare(b, order));
+    }
+
+    pub fn subjects(&self) -> impl Iterator<Item = i32> + '_ {
+        self.lanes.iter().map(|lane| lane.subject_id)
+    }
+
+    pub fn draw(&self, width: f64, height: f64, style: SwimmerStyle) -> SVG {
+        let font_family: String = style.font_family.unwrap_or("sans-serif".to_string());
+        let font_size: f64 = style.font_size.unwrap_or(height / 50.0);
+        let font_color: String = style.font_color.unwrap_or("#000".to_string());
+        let border: f64 = style.border.unwrap_or(height / 10.0);
+        let grade_colors: Vec<String> = style
+            .grade_colors
+            .filter(|colors| !colors.is_empty())
+            .unwrap_or_else(default_grade_colors);
+
+        let mut lanes: Vec<&SwimmerLane> = self.lanes.iter().collect();
+        if let Some(order) = style.lane_order {
+            lanes.sort_by(|a, b| a.compare(b, order));
+        }
+
+        // Initialise SVG
+
+        let mut document = SVG::new();
+
+        document.assign("viewBox", (0.0, 0.0, width, height));
+
+        document.append(Style::new(format!(
+            "line.lane {{
+	stroke: #0004;
+}}
+
+line.axis {{
+	stroke: #000F;
+}}
+
+text {{
+	fill: {font_color};
+	dominant-baseline: central;
+	font-family: {font_family};
+	font-size: {font_size}px;
+}}
+
+text.subject {{
+	text-anchor: end;
+}}
+
+text.dose {{
+	text-anchor: middle;
+}}"
+        )));
+
+        // Dose axis, with room for the subject labels to the left and the legend to the right
+
+        let max_dose = lanes
+            .iter()
+            .flat_map(|lane| lane.doses.iter().map(|&(dose, _)| dose))
+            .max()
+            .unwrap_or(1)
+            .max(1);
+        let plot_left = border + font_size * 6.0;
+        let plot_right = width - border - font_size * 6.0;
+        let plot_bottom = height - border;
+        let dose_spacing = match max_dose > 1 {
+            true => (plot_right - plot_left) / (max_dose - 1) as f64,
+            false => 0.0,
+        };
+        let dose_x = |dose: i32| plot_left + (dose - 1) as f64 * dose_spacing;
+
+        let lane_height = (plot_bottom - border) / lanes.len().max(1) as f64;
+        let marker_radius = style
+            .marker_radius
+            .unwrap_or((lane_height / 2.0 * 0.8).min(font_size / 2.0));
+
+        let mut axis = Group::new();
+        axis.assign("class", "axis");
+        axis.append(
+            Line::new()
+                .set("x1", plot_left)
+                .set("y1", plot_bottom)
+                .set("x2", plot_right)
+                .set("y2", plot_bottom)
+                .set("class", "axis"),
+        );
+        for dose in 1..=max_dose {
+            let mut text = Text::new();
+            text.assign("x", dose_x(dose));
+            text.assign("y", plot_bottom + font_size);
+            text.assign("class", "dose");
+            text.append(node::Text::new(format!("D{dose}")));
+            axis.append(text);
+        }
+
+        // Generate lanes
+
+        let mut svg_lanes = Vec::new();
+
+        for (index, lane) in lanes.iter().enumerate() {
+            let y = border + (index as f64 + 0.5) * lane_height;
+
+            let mut group = Group::new();
+            group.assign("class", "lane");
+            group.assign("data-subject", lane.subject_id);
+
+            if let (Some(&(first, _)), Some(&(last, _))) = (lane.doses.first(), lane.doses.last()) {
+                group.append(
+                    Line::new()
+                        .set("x1", dose_x(first))
+                        .set("y1", y)
+                        .set("x2", dose_x(last))
+                        .set("y2", y)
+                        .set("class", "lane"),
+                );
+            }
+
+            for &(dose, grade) in &lane.doses {
+                let color = &grade_colors[grade as usize % grade_colors.len()];
+                let mut marker = Circle::new();
+                marker.assign("cx", dose_x(dose));
+                marker.assign("cy", y);
+                marker.assign("r", marker_radius);
+                marker.assign("class", "dose");
+                marker.assign("data-dose", dose);
+                marker.assign("data-grade", grade.to_string());
+                marker.assign("style", format!("fill:{color}"));
+                group.append(marker);
+            }
+
+            let mut text = Text::new();
+            text.assign("x", plot_left - font_size);
+            text.assign("y", y);
+            text.assign("class", "subject");
+            // Labels would overlap once lanes are thinner than the text
+            if lane_height >= font_size {
+                text.append(node::Text::new(lane.subject_id.to_string()));
+                group.append(text);
+            }
+
+            svg_lanes.push(group);
+        }
+
+        // Generate legend
+
+        let mut legend = Group::new();
+        legend.assign("class", "legend");
+
+        for (index, grade) in CytokineReleaseSyndromeGrade::iter().enumerate() {
+            let y = border + index as f64 * font_size * 1.5;
+            let color = &grade_colors[grade as usize % grade_colors.len()];
+
+            let mut marker = Circle::new();
+            marker.assign("cx", plot_right + font_size * 2.0);
+            marker.assign("cy", y);
+            marker.assign("r", font_size / 2.0);
+            marker.assign("style", format!("fill:{color}"));
+            legend.append(marker);
+
+            let mut text = Text::new();
+            text.assign("x", plot_right + font_size * 3.0);
+            text.assign("y", y);
+            text.append(node::Text::new(grade.to_string()));
+            legend.append(text);
+        }
+
+        // Add to SVG
+
+        document.append(axis);
+
+        for lane in svg_lanes {
+            document.append(lane);
+        }
+
+        document.append(legend);
+
+        document
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(subject_id: i32, dose_number: i32, grade: i32) -> AeDoseCsvRecord {
+        AeDoseCsvRecord {
+            subject_id,
+            dose_number,
+            cytokine_release_syndrome_grade_id: grade,
+            date: String::new(),
+            time: None,
+        }
+    }
+
+    #[test]
+    fn sorts_lanes() {
+        let records = vec![
+            record(3, 1, 0),
+            record(1, 1, 0),
+            record(1, 2, 1),
+            record(2, 1, 2),
+            record(3, 2, 0),
+            record(3, 3, 0),
+        ];
+        let mut plot = SwimmerPlot::from_records(&records).unwrap();
+
+        plot.sort_lanes(LaneOrder::MaxGrade);
+        assert_eq!(plot.subjects().collect::<Vec<_>>(), vec![2, 1, 3]);
+        plot.sort_lanes(LaneOrder::DoseCount);
+        assert_eq!(plot.subjects().collect::<Vec<_>>(), vec![3, 1, 2]);
+        plot.sort_lanes(LaneOrder::SubjectId);
+        assert_eq!(plot.subjects().collect::<Vec<_>>(), vec![1, 2, 3]);
+
+        let svg = plot
+            .draw(
+                600.0,
+                400.0,
+                SwimmerStyle {
+                    lane_order: Some(LaneOrder::MaxGrade),
+                    ..SwimmerStyle::default()
+                },
+            )
+            .to_string();
+
+        assert_eq!(svg.matches("data-dose=").count(), 6);
+        assert!(svg.find("data-subject=\"2\"").unwrap() < svg.find("data-subject=\"1\"").unwrap());
+
+        // Without an order in the style, the lanes keep the order they were sorted in
+        plot.sort_lanes(LaneOrder::DoseCount);
+        let svg = plot.draw(600.0, 400.0, SwimmerStyle::default()).to_string();
+        assert!(svg.find("data-subject=\"3\"").unwrap() < svg.find("data-subject=\"1\"").unwrap());
+
+        // An empty list of colours falls back to the defaults
+        let svg = plot
+            .draw(
+                600.0,
+                400.0,
+                SwimmerStyle {
+                    grade_colors: Some(Vec::new()),
+                    ..SwimmerStyle::default()
+                },
+            )
+            .to_string();
+        let sankey_colors = CytokineReleaseSyndromeGrade::ordinal_scale().level_colors();
+        assert!(svg.contains(&format!("fill:{}", sankey_colors[2])));
+        assert!(SwimmerPlot::from_records(&[record(1, 1, 7)]).is_err());
+    }
+}
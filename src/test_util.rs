@@ -0,0 +1,49 @@
+//! Fixtures shared by the unit tests.
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A directory of its own for a test that has to go through the file system, removed when
+/// dropped. Tests run in parallel, so they must not share fixed file names.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> TempDir {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "charts-{}-{}",
+            std::process::id(),
+            COUNT.fetch_add(1, Ordering::Relaxed)
+        ));
+        std::fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes `contents` to `name` within the directory, and returns its path.
+    pub fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
+
+/// The x coordinate of the node rectangle with the given id in a drawn Sankey.
+pub fn node_x(svg: &str, id: &str) -> f64 {
+    let start = svg[..svg.find(&format!("id=\"{id}\"")).unwrap()]
+        .rfind("<rect")
+        .unwrap();
+    let rect = &svg[start..start + svg[start..].find('>').unwrap()];
+    let x = &rect[rect.find(" x=\"").unwrap() + 4..];
+    x[..x.find('"').unwrap()].parse().unwrap()
+}
@@ -0,0 +1,163 @@
+use std::{fs::File, io::Read, path::Path};
+
+use serde::{Deserialize, Serialize};
+
+use crate::file_op::JsonReadError;
+
+static LIGHT: &str = include_str!("../assets/themes/light.json");
+static DARK: &str = include_str!("../assets/themes/dark.json");
+static PRINT: &str = include_str!("../assets/themes/print.json");
+static COLOUR_BLIND: &str = include_str!("../assets/themes/colour-blind.json");
+
+/// The colours and fonts of a chart. The built-in themes are JSON files under `assets/themes`, and
+/// custom ones can be loaded with [`Theme::from_json_file`]. Fields missing from a theme file are
+/// taken from the light theme.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Theme {
+    pub name: String,
+    /// Fill behind the whole chart. Transparent if unset.
+    pub background: Option<String>,
+    pub node_fill: String,
+    pub node_stroke: Option<String>,
+    /// Colour of the ribbons without a colour of their own, drawn at `ribbon_opacity`.
+    pub ribbon_color: String,
+    pub ribbon_opacity: f64,
+    pub font_family: String,
+    pub font_color: String,
+    /// Colours given in turn to the nodes without a colour of their own. If empty, they are
+    /// filled with `node_fill`.
+    pub palette: Vec<String>,
+}
+
+impl Theme {
+    pub fn light() -> Theme {
+        Theme::parse(LIGHT)
+    }
+
+    pub fn dark() -> Theme {
+        Theme::parse(DARK)
+    }
+
+    /// Greyscale, with outlined nodes, for printing.
+    pub fn print() -> Theme {
+        Theme::parse(PRINT)
+    }
+
+    /// Colours nodes with the Okabe-Ito palette, which stays distinguishable with the common
+    /// forms of colour vision deficiency.
+    pub fn colour_blind() -> Theme {
+        Theme::parse(COLOUR_BLIND)
+    }
+
+    /// Looks up a built-in theme: "light", "dark", "print" or "colour-blind".
+    pub fn named(name: &str) -> Option<Theme> {
+        match name {
+            "light" => Some(Theme::light()),
+            "dark" => Some(Theme::dark()),
+            "print" => Some(Theme::print()),
+            "colour-blind" => Some(Theme::colour_blind()),
+            _ => None,
+        }
+    }
+
+    pub fn from_json_file<P: AsRef<Path>>(path: P) -> Result<Theme, JsonReadError> {
+        let mut file = File::open(path)?;
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+
+        Ok(serde_json::from_str(&contents)?)
+    }
+
+    /// Palette colour for the `index`th item, cycling through the palette.
+    pub fn palette_color(&self, index: usize) -> Option<&str> {
+        match self.palette.is_empty() {
+            true => None,
+            false => Some(&self.palette[index % self.palette.len()]),
+        }
+    }
+
+    // The built-in themes are checked by the tests, so they always parse
+    fn parse(json: &str) -> Theme {
+        serde_json::from_str(json).expect("built-in theme is valid JSON")
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            name: "light".to_string(),
+            background: None,
+            node_fill: "#000".to_string(),
+            node_stroke: None,
+            ribbon_color: "#000".to_string(),
+            ribbon_opacity: 0.25,
+            font_family: "sans-serif".to_string(),
+            font_color: "#000".to_string(),
+            palette: Vec::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sankey_graph::{Sankey, SankeyStyle};
+    use crate::test_util::TempDir;
+
+    #[test]
+    fn built_in_and_custom_themes_load() {
+        for name in ["light", "dark", "print", "colour-blind"] {
+            assert_eq!(Theme::named(name).unwrap().name, name);
+        }
+        assert_eq!(Theme::light(), Theme::default());
+        assert!(Theme::named("sepia").is_none());
+
+        let temp = TempDir::new();
+        let path = temp.write(
+            "custom.json",
+            r##"{"name": "custom", "palette": ["#123", "#456"]}"##,
+        );
+        let theme = Theme::from_json_file(&path).unwrap();
+        assert!(matches!(
+            Theme::from_json_file(temp.path().join("missing.json")),
+            Err(JsonReadError::Io(_))
+        ));
+
+        // Unset fields fall back to the light theme
+        assert_eq!(theme.node_fill, "#000");
+        assert_eq!(theme.palette_color(3), Some("#456"));
+        assert_eq!(Theme::light().palette_color(0), None);
+
+        let mut sankey = Sankey::new();
+        let a = sankey.node(None, None, None);
+        let b = sankey.node(None, None, Some("#F00".to_string()));
+        sankey.edge(a, b, 1.0, None, None);
+        let svg = sankey
+            .draw(
+                400.0,
+                300.0,
+                SankeyStyle::<fn(f64) -> String> {
+                    theme: Some(theme),
+                    ..SankeyStyle::default()
+                },
+            )
+            .to_string();
+
+        // The palette only colours nodes without a colour of their own
+        assert!(svg.contains("fill:#123"));
+        assert!(svg.contains("fill:#F00"));
+        assert!(!svg.contains("class=\"background\""));
+        let svg = sankey
+            .draw(
+                400.0,
+                300.0,
+                SankeyStyle::<fn(f64) -> String> {
+                    theme: Theme::named("dark"),
+                    ..SankeyStyle::default()
+                },
+            )
+            .to_string();
+        assert!(svg.contains("fill:#1E1E1E"));
+    }
+}